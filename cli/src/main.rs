@@ -0,0 +1,477 @@
+//! Headless runner: executes a ROM for a number of frames and exports the resulting screen.
+
+use chip8_core::analysis;
+use chip8_core::config::EmulatorConfig;
+use chip8_db::{ Database, RomInfo };
+use chip8_core::filter::{ Persistence, PhosphorFilter };
+use chip8_core::font::Font;
+use chip8_core::gif::GifRecorder;
+use chip8_core::layout::MemoryLayout;
+use chip8_core::machine::Machine;
+use chip8_core::megachip::MegaChip;
+use chip8_core::platform::Platform;
+use chip8_core::recompiler::Engine;
+use chip8_core::render::{ Frame, Intensities, Palette, Renderer };
+use chip8_core::scale::Scaler;
+use chip8_core::screenshot;
+use chip8_core::state::ExecutionState;
+use chip8_core::sys::SysPolicy;
+use chip8_core::timing::Timing;
+use chip8_core::translate;
+use chip8_core::vip::Vip;
+
+use std::env;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{ Path, PathBuf };
+use std::process;
+
+const USAGE: &str = "\
+usage: chip8 <rom> [options]
+
+options:
+    --frames <count>      number of frames to run (default: 60)
+    --ipf <count>         instructions executed per frame (default: 10, or the one recommended
+                          for known ROMs)
+    --timing <mode>       instructions, running --ipf instructions per frame, or vip, running
+                          as many as the COSMAC VIP would (default: instructions)
+    --engine <engine>     interpreter, or recompiler to execute blocks of instructions compiled
+                          to closures, faster with the same results (default: interpreter)
+    --layout <layout>     separate, or vip to map the registers, stack and display in memory
+                          at their COSMAC VIP addresses (default: separate)
+    --platform <id>       platform to emulate, as identified in the community database, or
+                          chip8Hires (default: guessed from the ROM)
+    --font <name>         font set: octo, vip, dream6800, eti660 or fish (default: octo)
+    --screenshot <file>   write the screen once done, as .png, .pbm or .pgm
+    --record <file>       record every frame as an animated GIF
+    --scale <factor>      scale factor of the screenshot and recording (default: 1)
+    --scaler <name>       pixel-art upscaler of the screenshot and recording, applied before
+                          --scale: epx, scale2x or scale3x (default: none)
+    --phosphor <frames>   keep pixels lit for the last 1 to 32 frames in the screenshot and
+                          recording, hiding the flicker of sprites
+    --ignore-sys          skip 0NNN machine code calls instead of stopping
+    --vip <monitor> <interpreter>
+                          run the original interpreter on an emulated COSMAC VIP, from images
+                          of its monitor ROM and of the interpreter
+    --compare             with --vip, also interpret the ROM as the original CHIP-8 and report
+                          the first frame where both differ
+    --translate <file>    write the ROM translated to a Rust module instead of running it";
+
+struct Options {
+    rom: PathBuf,
+    frames: usize,
+    instructions_per_frame: Option<usize>,
+    timing: Timing,
+    engine: Engine,
+    layout: MemoryLayout,
+    platform: Option<Platform>,
+    font: Font,
+    screenshot: Option<PathBuf>,
+    record: Option<PathBuf>,
+    scale: NonZeroUsize,
+    scaler: Scaler,
+    phosphor: Option<u8>,
+    sys_policy: SysPolicy,
+    vip: Option<(PathBuf, PathBuf)>,
+    compare: bool,
+    translate: Option<PathBuf>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut arguments = env::args().skip(1);
+
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: 60,
+        instructions_per_frame: None,
+        timing: Timing::Instructions,
+        engine: Engine::Interpreter,
+        layout: MemoryLayout::Separate,
+        platform: None,
+        font: Font::Octo,
+        screenshot: None,
+        record: None,
+        scale: NonZeroUsize::MIN,
+        scaler: Scaler::default(),
+        phosphor: None,
+        sys_policy: SysPolicy::Fault,
+        vip: None,
+        compare: false,
+        translate: None,
+    };
+
+    while let Some(argument) = arguments.next() {
+        let mut value = |name: &str| {
+            arguments.next().ok_or_else(|| format!("missing value for {}", name))
+        };
+        let number = |name: &str, value: String| {
+            value.parse::<usize>().map_err(|_| format!("invalid value for {}: {}", name, value))
+        };
+
+        match argument.as_str() {
+            "--frames" => options.frames = number("--frames", value("--frames")?)?,
+            "--ipf" => options.instructions_per_frame = Some(number("--ipf", value("--ipf")?)?),
+            "--timing" => {
+                options.timing = match value("--timing")?.as_str() {
+                    "instructions" => Timing::Instructions,
+                    "vip" => Timing::Vip,
+                    timing => return Err(format!("unknown timing {}", timing)),
+                };
+            },
+            "--engine" => {
+                options.engine = match value("--engine")?.as_str() {
+                    "interpreter" => Engine::Interpreter,
+                    "recompiler" => Engine::Recompiler,
+                    engine => return Err(format!("unknown engine {}", engine)),
+                };
+            },
+            "--layout" => {
+                options.layout = match value("--layout")?.as_str() {
+                    "separate" => MemoryLayout::Separate,
+                    "vip" => MemoryLayout::Vip,
+                    layout => return Err(format!("unknown layout {}", layout)),
+                };
+            },
+            "--platform" => {
+                let id = value("--platform")?;
+                let platform = Platform::from_id(&id)
+                    .ok_or_else(|| format!("unknown platform {}", id))?;
+                options.platform = Some(platform);
+            },
+            "--font" => {
+                let name = value("--font")?;
+                options.font = Font::from_name(&name)
+                    .ok_or_else(|| format!("unknown font {}", name))?;
+            },
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value("--screenshot")?)),
+            "--record" => options.record = Some(PathBuf::from(value("--record")?)),
+            "--scale" => {
+                let scale = number("--scale", value("--scale")?)?;
+                options.scale = NonZeroUsize::new(scale)
+                    .ok_or_else(|| "--scale must be at least 1".to_string())?;
+            },
+            "--scaler" => {
+                options.scaler = match value("--scaler")?.as_str() {
+                    "epx" => Scaler::Epx,
+                    "scale2x" => Scaler::Scale2x,
+                    "scale3x" => Scaler::Scale3x,
+                    scaler => return Err(format!("unknown scaler {}", scaler)),
+                };
+            },
+            "--phosphor" => {
+                let frames = number("--phosphor", value("--phosphor")?)?;
+                if !(1..=32).contains(&frames) {
+                    return Err(format!("--phosphor must be between 1 and 32 frames: {}", frames));
+                }
+                options.phosphor = Some(frames as u8);
+            },
+            "--ignore-sys" => options.sys_policy = SysPolicy::Ignore,
+            "--vip" => {
+                let monitor = PathBuf::from(value("--vip")?);
+                options.vip = Some((monitor, PathBuf::from(value("--vip")?)));
+            },
+            "--compare" => options.compare = true,
+            "--translate" => options.translate = Some(PathBuf::from(value("--translate")?)),
+            "-h" | "--help" => return Err(String::new()),
+            _ if rom.is_none() && !argument.starts_with("--") => rom = Some(PathBuf::from(argument)),
+            _ => return Err(format!("unexpected argument {}", argument)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| String::from("missing rom"))?;
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom)
+        .map_err(|error| format!("couldn't read {}: {}", options.rom.display(), error))?;
+
+    let known = Database::embedded().identify(&rom);
+
+    if let Some(path) = &options.translate {
+        // The ROM is translated where it would be loaded to run it.
+        let mut config = EmulatorConfig::new();
+        if let Some(info) = &known {
+            config = info.configure(config);
+        }
+        if let Some(platform) = options.platform {
+            config = config.platform(platform);
+        }
+
+        let start = config.get_start_address();
+        return fs::write(path, translate::translate(&rom, start))
+            .map_err(|error| format!("couldn't write {}: {}", path.display(), error));
+    }
+
+    if let Some((monitor, interpreter)) = &options.vip {
+        return run_vip(&options, &rom, monitor, interpreter);
+    }
+
+    // MegaChip ROMs can be larger than the memory of the other platforms, they are told apart
+    // before being loaded.
+    let platform = options.platform.or_else(|| known.as_ref().and_then(|info| info.platform()));
+
+    if platform == Some(Platform::MegaChip8) {
+        return run_megachip(&options, &rom, known);
+    }
+
+    let mut program = EmulatorConfig::new()
+        .font(options.font)
+        .memory_layout(options.layout)
+        .engine(options.engine)
+        .build()
+        .map_err(|error| error.to_string())?;
+    let (_, info) = Database::embedded().load(&mut program, &rom)
+        .map_err(|error| format!("couldn't load {}: {}", options.rom.display(), error))?;
+    program.set_sys_policy(options.sys_policy);
+
+    // The platform picked after loading may have programs start elsewhere, as CHIP-8X does, in
+    // which case the ROM is loaded again.
+    let mut platform = options.platform;
+
+    if let Some(platform) = platform {
+        program.set_platform(platform);
+    }
+
+    if let Some(count) = options.instructions_per_frame {
+        program.set_instructions_per_frame(count);
+    }
+
+    program.set_timing(options.timing);
+
+    match &info {
+        Some(info) => eprintln!("identified {}", info.title),
+        None if options.platform.is_some() => {},
+        None => {
+            let analysis = analysis::analyze(&rom, program.config().get_start_address());
+            eprintln!("detected {} (confidence {:.2})", analysis.platform, analysis.confidence);
+
+            if analysis.is_confident() {
+                program.set_platform(analysis.platform);
+                program.set_quirks(analysis.quirks);
+                platform = Some(analysis.platform);
+            }
+        },
+    }
+
+    if platform.is_some() && program.config().get_start_address() != program.program_counter {
+        program.load(&rom)
+            .map_err(|error| format!("couldn't load {}: {}", options.rom.display(), error))?;
+    }
+
+    let palette = info.and_then(|info| info.palette).unwrap_or(Palette::DEFAULT);
+    let renderer = Renderer::new(options.scale, palette);
+    let mut recorder = options.record.as_ref().map(|_| GifRecorder::new(renderer));
+    let mut filter = options.phosphor
+        .map(|frames| PhosphorFilter::new(Persistence::Blend { frames }))
+        .transpose()
+        .map_err(|error| error.to_string())?;
+
+    for _ in 0..options.frames {
+        program.run_frame();
+
+        if let Some(filter) = filter.as_mut() {
+            filter.update(&program.screen);
+        }
+
+        if let Some(recorder) = recorder.as_mut() {
+            match &filter {
+                Some(filter) => recorder.capture(&options.scaler.scale(&Lit(filter.intensities()))),
+                None => recorder.capture(&options.scaler.scale(&program.screen)),
+            }
+        }
+    }
+
+    if let (Some(path), Some(recorder)) = (&options.record, recorder) {
+        fs::write(path, recorder.encode())
+            .map_err(|error| format!("couldn't write {}: {}", path.display(), error))?;
+    }
+
+    if let Some(path) = options.screenshot {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let filtered = filter.as_ref().map(|filter| Lit(filter.intensities()));
+        let frame: &dyn Frame = match &filtered {
+            Some(filtered) => filtered,
+            None => &program.screen,
+        };
+        let scaled = options.scaler.scale(frame);
+
+        let image = match extension {
+            // The colour board only shows in PNG screenshots, the other formats are grayscale.
+            Some("png") if program.platform() == Some(Platform::Chip8X) => {
+                // Upscalers work on pixels being lit or not, they can't tell the colours apart.
+                if options.scaler != Scaler::default() {
+                    return Err(String::from("--scaler doesn't apply to CHIP-8X colours"));
+                }
+
+                let (width, height) = renderer.dimensions(&program.screen);
+                let pixels = renderer.render_rgba(
+                    program.screen.width(),
+                    &program.colors.to_rgba(&program.screen)
+                );
+
+                screenshot::encode_png(width, height, &pixels)
+            },
+            Some("png") => screenshot::png(&scaled, &renderer),
+            Some("pbm") => screenshot::pbm(&scaled, options.scale),
+            Some("pgm") => screenshot::pgm(&scaled, &renderer),
+            _ => return Err(format!("unsupported screenshot format {}", path.display())),
+        };
+
+        fs::write(&path, image)
+            .map_err(|error| format!("couldn't write {}: {}", path.display(), error))?;
+    }
+
+    report(program.state())
+}
+
+/// Pixels lit in the output of a [`PhosphorFilter`] blending frames, whose intensities are
+/// either 0 or 255.
+struct Lit<'a>(&'a Intensities);
+
+impl Frame for Lit<'_> {
+    fn width(&self) -> usize {
+        self.0.width()
+    }
+
+    fn height(&self) -> usize {
+        self.0.height()
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        (self.0.value(x, y) != 0) as u8
+    }
+}
+
+/// Run a MegaChip ROM, whose colour display can only be exported as PNG.
+fn run_megachip(options: &Options, rom: &[u8], info: Option<RomInfo>) -> Result<(), String> {
+    if options.record.is_some() {
+        return Err(String::from("recording MegaChip programs isn't supported"));
+    }
+
+    if options.scaler != Scaler::default() {
+        return Err(String::from("--scaler doesn't apply to the MegaChip colour display"));
+    }
+
+    let mut config = EmulatorConfig::new().font(options.font);
+    if let Some(info) = &info {
+        eprintln!("identified {}", info.title);
+        config = info.configure(config);
+    }
+
+    if let Some(count) = options.instructions_per_frame {
+        config = config.instructions_per_frame(count);
+    }
+
+    let mut megachip = MegaChip::with_config(config).map_err(|error| error.to_string())?;
+    megachip.load(rom)
+        .map_err(|error| format!("couldn't load {}: {}", options.rom.display(), error))?;
+    megachip.program_mut().set_sys_policy(options.sys_policy);
+
+    for _ in 0..options.frames {
+        megachip.run_frame();
+    }
+
+    if let Some(path) = &options.screenshot {
+        if path.extension().and_then(|extension| extension.to_str()) != Some("png") {
+            return Err(format!("unsupported screenshot format {}", path.display()));
+        }
+
+        let palette = info.and_then(|info| info.palette).unwrap_or(Palette::DEFAULT);
+        let (width, height) = megachip.dimensions();
+        let pixels = Renderer::new(options.scale, palette)
+            .render_rgba(width, &megachip.to_rgba(palette));
+        let scale = options.scale.get();
+        let image = screenshot::encode_png(width * scale, height * scale, &pixels);
+
+        fs::write(path, image)
+            .map_err(|error| format!("couldn't write {}: {}", path.display(), error))?;
+    }
+
+    report(megachip.state())
+}
+
+/// Run the original interpreter on an emulated VIP, optionally checking the high-level
+/// interpreter against it.
+fn run_vip(options: &Options, rom: &[u8], monitor: &Path, interpreter: &Path)
+    -> Result<(), String>
+{
+    let read = |path: &Path| {
+        fs::read(path).map_err(|error| format!("couldn't read {}: {}", path.display(), error))
+    };
+
+    let mut vip = Vip::new(&read(monitor)?, &read(interpreter)?)
+        .map_err(|error| format!("invalid VIP images: {}", error))?;
+    vip.load(rom)
+        .map_err(|error| format!("couldn't load {}: {}", options.rom.display(), error))?;
+
+    let mut program = EmulatorConfig::vip()
+        .platform(Platform::OriginalChip8)
+        .timing(Timing::Vip)
+        .memory_layout(MemoryLayout::Vip)
+        .build()
+        .map_err(|error| error.to_string())?;
+    program.load(rom)
+        .map_err(|error| format!("couldn't load {}: {}", options.rom.display(), error))?;
+    program.set_sys_policy(options.sys_policy);
+
+    for frame in 0..options.frames {
+        vip.run_frame();
+
+        if options.compare {
+            program.run_frame();
+
+            if program.snapshot() != vip.snapshot() {
+                eprintln!("frame {}: interpreter {:?}", frame, program.snapshot());
+                eprintln!("frame {}: VIP {:?}", frame, vip.snapshot());
+                return Err(format!("interpreters differ at frame {}", frame));
+            }
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        let renderer = Renderer::new(options.scale, Palette::DEFAULT);
+        let scaled = options.scaler.scale(vip.screen());
+        let image = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("png") => screenshot::png(&scaled, &renderer),
+            Some("pbm") => screenshot::pbm(&scaled, options.scale),
+            Some("pgm") => screenshot::pgm(&scaled, &renderer),
+            _ => return Err(format!("unsupported screenshot format {}", path.display())),
+        };
+
+        fs::write(path, image)
+            .map_err(|error| format!("couldn't write {}: {}", path.display(), error))?;
+    }
+
+    Ok(())
+}
+
+fn report(state: ExecutionState) -> Result<(), String> {
+    match state {
+        ExecutionState::Halted(reason) => eprintln!("program {}", reason),
+        ExecutionState::Faulted(fault) => return Err(format!("program faulted: {}", fault)),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("error: {}\n", error);
+            }
+
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(error) = run(options) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,50 @@
+//! Frames run as fast as possible, as done for batch testing.
+
+use chip8_core::config::EmulatorConfig;
+use chip8_core::program::Register;
+use chip8_core::recompiler::Engine;
+
+use criterion::{ black_box, criterion_group, criterion_main, Criterion };
+
+/// Draws a sprite moving across the screen, counting with BCD in a subroutine, forever.
+const ROM: [u8; 26] = [
+    0x66, 0x00, 0x67, 0x00, // v6 = 0, v7 = 0
+    0xA2, 0x18, 0xD6, 0x72, // I = sprite, draw at (v6, v7)
+    0x22, 0x10, 0x76, 0x01, // call 0x210, v6 += 1
+    0x77, 0x01, 0x12, 0x04, // v7 += 1, jump 0x204
+    0xA3, 0x00, 0xF6, 0x33, // I = 0x300, BCD of v6
+    0xF2, 0x65, 0x00, 0xEE, // read the digits to v0..v2, return
+    0xF0, 0x90,             // sprite
+];
+
+const FRAMES: usize = 10_000;
+
+fn frames(c: &mut Criterion) {
+    let configs = [
+        ("frames/decode cache", EmulatorConfig::new()),
+        ("frames/no decode cache", EmulatorConfig::new().decode_cache(false)),
+        ("frames/recompiler", EmulatorConfig::new().engine(Engine::Recompiler)),
+    ];
+
+    for &(name, config) in &configs {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let mut program = config.instructions_per_frame(1000).build().unwrap();
+                program.load(&ROM).unwrap();
+
+                for _ in 0..FRAMES {
+                    program.run_frame();
+                }
+
+                black_box(program.register(Register::V6))
+            })
+        });
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = frames
+}
+criterion_main!(benches);
@@ -0,0 +1,277 @@
+//! Guessing the platform and quirks of ROMs the database doesn't know.
+//!
+//! The instructions reachable from the start address are followed like the interpreter would,
+//! both branches of skips and calls being explored, and their opcodes are looked at for the
+//! extensions of each platform.
+
+use crate::instructions::Instruction;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+use alloc::collections::BTreeSet;
+use alloc::{ vec, vec::Vec };
+
+/// Confidence above which the result is worth acting upon.
+pub const CONFIDENT: f32 = 0.6;
+
+/// Reason to believe a quirk has a given value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuirkHint {
+    /// Name of the quirk, as in the community database.
+    pub quirk: &'static str,
+    pub value: bool,
+    /// Address of the instruction relying on the quirk.
+    pub address: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    /// The most likely platform.
+    pub platform: Platform,
+    /// How sure the guess is, from 0 to 1.
+    pub confidence: f32,
+    /// Quirks of the platform, adjusted by the hints.
+    pub quirks: Quirks,
+    pub hints: Vec<QuirkHint>,
+    /// Number of instructions reachable from the start address.
+    pub reachable: usize,
+}
+
+impl Analysis {
+    /// Whether the guess is good enough to pick the defaults of a frontend.
+    pub fn is_confident(&self) -> bool {
+        self.confidence >= CONFIDENT
+    }
+}
+
+/// Instructions of the extensions found in the reachable code.
+#[derive(Default)]
+struct Features {
+    superchip: usize,
+    xo_chip: usize,
+    chip8x: usize,
+    machine_code: usize,
+    /// Instructions invalid on every platform.
+    invalid: usize,
+}
+
+/// Analyze `rom`, loaded at `start` where execution begins.
+pub fn analyze(rom: &[u8], start: u16) -> Analysis {
+    let opcode = |address: u16| -> Option<u16> {
+        let offset = address.checked_sub(start)? as usize;
+        let bytes = rom.get(offset..offset + 2)?;
+
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    // The patch of the hires interpreter isn't CHIP-8 code, the program itself starts at 0x2C0.
+    let hires = opcode(start) == Some(0x1260) && start == 0x200;
+
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![if hires { 0x2C0 } else { start }];
+    let mut features = Features::default();
+    let mut hints = Vec::new();
+
+    while let Some(address) = pending.pop() {
+        let code = match opcode(address) {
+            Some(code) if visited.insert(address) => code,
+            _ => continue,
+        };
+
+        // XO-CHIP `F000 NNNN` is 4 bytes long, skips jump over it entirely.
+        let next = address.wrapping_add(2);
+        let skip = next.wrapping_add(if opcode(next) == Some(0xF000) { 4 } else { 2 });
+
+        let nibbles = (
+            (code >> 12) as u8,
+            (code >> 8) as u8 & 0xF,
+            (code >> 4) as u8 & 0xF,
+            code as u8 & 0xF
+        );
+
+        match nibbles {
+            (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB..=0xF) => features.superchip += 1,
+            (0xF, _, 0x3, 0x0) | (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => features.superchip += 1,
+            (0xD, _, _, 0x0) => features.superchip += 1,
+            (0x0, 0x0, 0xD, _) | (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => features.xo_chip += 1,
+            (0xF, 0x0, 0x0, 0x0) | (0xF, 0x0, 0x0, 0x2) => features.xo_chip += 1,
+            (0xF, _, 0x0, 0x1) | (0xF, _, 0x3, 0xA) => features.xo_chip += 1,
+            (0x5, _, _, 0x1) | (0xE, _, 0xF, 0x2) | (0xE, _, 0xF, 0x5) => features.chip8x += 1,
+            (0x0, 0x2, 0xA, 0x0) => features.chip8x += 1,
+            _ => {},
+        }
+
+        match Instruction::from(code) {
+            Instruction::ReturnSubroutine(_) | Instruction::Exit(_) => {},
+            Instruction::Sys(_) => {
+                // 00CN, 00DN and 00FB to 00FF are SCHIP and XO-CHIP instructions, 0230 clears
+                // the hires display and 02A0 cycles the CHIP-8X background.
+                let known = matches!(code, 0x00C0..=0x00DF | 0x00FB..=0x00FF | 0x02A0)
+                    || (hires && code == 0x0230);
+
+                if !known {
+                    features.machine_code += 1;
+                }
+
+                pending.push(next);
+            },
+            Instruction::JumpTo(jump) => pending.push(jump.address),
+            Instruction::CallSubroutine(call) => {
+                pending.push(call.address);
+                pending.push(next);
+            },
+            Instruction::SkipEqual(_)
+            | Instruction::SkipNotEqual(_)
+            | Instruction::SkipRegisterEqual(_)
+            | Instruction::SkipRegisterNotEqual(_)
+            | Instruction::SkipKeyPressed(_)
+            | Instruction::SkipKeyNotPressed(_)
+            | Instruction::SkipSecondKeyPressed(_)
+            | Instruction::SkipSecondKeyNotPressed(_) => {
+                pending.push(next);
+                pending.push(skip);
+            },
+            // The target depends on a register, the code after it can't be known.
+            Instruction::JumpToPlusV0(_) => {},
+            Instruction::SetVxToVxShr(shift) if shift.x != shift.y => {
+                hints.push(QuirkHint { quirk: "shift", value: false, address });
+                pending.push(next);
+            },
+            Instruction::SetVxToVxShl(shift) if shift.x != shift.y => {
+                hints.push(QuirkHint { quirk: "shift", value: false, address });
+                pending.push(next);
+            },
+            Instruction::InvalidInstruction(_) => {
+                let extension = matches!(nibbles.0, 0x5 | 0xD | 0xF);
+
+                if !extension {
+                    features.invalid += 1;
+                } else if code == 0xF000 {
+                    pending.push(address.wrapping_add(4));
+                } else {
+                    pending.push(next);
+                }
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    hints.extend(memory_loops(&visited, opcode));
+    hints.sort_by_key(|hint| hint.address);
+
+    let reachable = visited.len();
+
+    let (platform, distinctive) = if features.xo_chip > 0 {
+        (Platform::XoChip, features.xo_chip)
+    } else if features.superchip > 0 {
+        (Platform::Superchip, features.superchip)
+    } else if features.chip8x > 0 {
+        (Platform::Chip8X, features.chip8x)
+    } else if hires {
+        (Platform::HiresChip8, 1)
+    } else if features.machine_code > 0 {
+        (Platform::HybridVip, features.machine_code)
+    } else {
+        (Platform::ModernChip8, 0)
+    };
+
+    // Instructions valid on the guessed platform, scaled by how much evidence backs it: a plain
+    // CHIP-8 ROM only becomes convincing once enough code was seen without any extension.
+    let valid = match reachable {
+        0 => 0.0,
+        _ => 1.0 - features.invalid as f32 / reachable as f32,
+    };
+    let evidence = if distinctive > 0 {
+        1.0 - 1.0 / (2.0 + distinctive as f32)
+    } else {
+        0.5 + 0.5 * reachable.min(128) as f32 / 128.0
+    };
+
+    let mut quirks = platform.quirks();
+    for hint in &hints {
+        quirks.set(hint.quirk, hint.value);
+    }
+
+    Analysis { platform, confidence: valid * evidence, quirks, hints, reachable }
+}
+
+/// Hints for `FX55` and `FX65` in loops that don't set I themselves, which only work if I is
+/// incremented.
+fn memory_loops<F>(visited: &BTreeSet<u16>, opcode: F) -> Vec<QuirkHint>
+where
+    F: Fn(u16) -> Option<u16>
+{
+    let mut hints = Vec::new();
+
+    for &address in visited {
+        let target = match opcode(address) {
+            Some(code) if code >> 12 == 0x1 && code & 0xFFF < address => code & 0xFFF,
+            _ => continue,
+        };
+
+        let body: Vec<(u16, u16)> = visited.range(target..address)
+            .filter_map(|&address| opcode(address).map(|code| (address, code)))
+            .collect();
+
+        let sets_i = body.iter().any(|&(_, code)| code >> 12 == 0xA);
+
+        if !sets_i {
+            for &(address, code) in &body {
+                if code & 0xF0FF == 0xF055 || code & 0xF0FF == 0xF065 {
+                    hints.push(QuirkHint { quirk: "memoryLeaveIUnchanged", value: false, address });
+                }
+            }
+        }
+    }
+
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_extensions() {
+        // cls, hires, jump to self.
+        let analysis = analyze(&[0x00, 0xE0, 0x00, 0xFF, 0x12, 0x04], 0x200);
+        assert_eq!(analysis.platform, Platform::Superchip);
+        assert_eq!(analysis.reachable, 3);
+
+        // long I load skipped over by a skip.
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x08, 0x00, 0xFD];
+        let analysis = analyze(&rom, 0x200);
+        assert_eq!(analysis.platform, Platform::XoChip);
+        assert!(analysis.is_confident());
+
+        let analysis = analyze(&[0x12, 0x60], 0x200);
+        assert_eq!(analysis.platform, Platform::HiresChip8);
+
+        // background cycling, then a second keypad skip.
+        let analysis = analyze(&[0x02, 0xA0, 0xE0, 0xF2, 0x12, 0x02], 0x200);
+        assert_eq!(analysis.platform, Platform::Chip8X);
+    }
+
+    #[test]
+    fn plain_chip8() {
+        let analysis = analyze(&[0x60, 0x01, 0x12, 0x02], 0x200);
+
+        assert_eq!(analysis.platform, Platform::ModernChip8);
+        assert!(!analysis.is_confident());
+        assert_eq!(analysis.quirks, Quirks::NONE);
+    }
+
+    #[test]
+    fn quirk_hints() {
+        // A loop storing v0 without setting I, then v1 >>= v2 shifting another register.
+        let rom = [0x60, 0x00, 0xF0, 0x55, 0x70, 0x01, 0x12, 0x02, 0x81, 0x26];
+        let analysis = analyze(&rom, 0x200);
+
+        let hint = QuirkHint { quirk: "memoryLeaveIUnchanged", value: false, address: 0x202 };
+        assert_eq!(analysis.hints, vec![hint]);
+
+        let analysis = analyze(&[0x81, 0x26, 0x12, 0x02], 0x200);
+        assert_eq!(analysis.hints[0].quirk, "shift");
+        assert!(!analysis.quirks.shift);
+    }
+}
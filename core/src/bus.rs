@@ -0,0 +1,264 @@
+//! Memory accesses of instructions.
+//!
+//! Instructions never index the memory of a [`Program`] directly, they go through its [`Bus`]
+//! implementation. By default it only checks bounds and counts accesses, but embedders can
+//! choose what happens to addresses past the end of the memory with an [`AddressPolicy`],
+//! protect regions from writes, and observe or alter every access with a [`BusHook`].
+//!
+//! Instruction fetches don't go through the bus.
+
+use crate::program::Program;
+use crate::state::Fault;
+
+#[cfg(feature = "alloc")]
+use alloc::{ boxed::Box, vec::Vec };
+
+#[cfg(feature = "alloc")]
+use core::ops::Range;
+
+/// What happens to accesses past the end of the memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressPolicy {
+    /// Stop the program with a [`Fault::MemoryOutOfBounds`].
+    #[default]
+    Fault,
+    /// Wrap around to the start of the memory.
+    Wrap,
+    /// Keep only the bits of the address in the mask, mirroring the memory like an incomplete
+    /// address bus would. Addresses still out of bounds fault.
+    Mask(usize),
+}
+
+pub trait Bus {
+    /// Read the byte at `address`.
+    fn read(&mut self, address: usize) -> Result<u8, Fault>;
+    /// Write `value` at `address`.
+    fn write(&mut self, address: usize, value: u8) -> Result<(), Fault>;
+}
+
+/// Observer of the memory accesses of instructions.
+#[cfg(feature = "alloc")]
+pub trait BusHook {
+    /// Called once `value` is read at `address`, returning what the instruction gets.
+    fn read(&mut self, address: usize, value: u8) -> u8 {
+        let _ = address;
+        value
+    }
+
+    /// Called before `value` is written at `address`, returning what is actually written, or
+    /// `None` to drop the write.
+    fn write(&mut self, address: usize, value: u8) -> Option<u8> {
+        let _ = address;
+        Some(value)
+    }
+}
+
+/// Accesses made through the bus, counted since the program was created or the counters reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounters {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// Bus settings of a program.
+#[derive(Default)]
+pub(crate) struct BusState {
+    pub(crate) policy: AddressPolicy,
+    #[cfg(feature = "alloc")]
+    pub(crate) protected: Vec<Range<usize>>,
+    #[cfg(feature = "alloc")]
+    pub(crate) hook: Option<Box<dyn BusHook>>,
+    pub(crate) counters: AccessCounters,
+}
+
+impl Program<'_> {
+    /// Where `address` points in memory, according to the address policy.
+    fn resolve(&self, address: usize) -> Result<usize, Fault> {
+        let size = self.config.memory_size;
+        let resolved = match self.bus.policy {
+            AddressPolicy::Fault => address,
+            AddressPolicy::Wrap => address % size,
+            AddressPolicy::Mask(mask) => address & mask,
+        };
+
+        if resolved < size {
+            Ok(resolved)
+        } else {
+            Err(Fault::MemoryOutOfBounds { address: self.program_counter, access: address })
+        }
+    }
+
+    /// Check the `length` bytes from `start` can all be accessed, so that instructions touching
+    /// several bytes fault before modifying anything.
+    pub(crate) fn check_access(&self, start: usize, length: usize, write: bool)
+        -> Result<(), Fault>
+    {
+        if self.bus.policy == AddressPolicy::Fault {
+            if start + length > self.config.memory_size {
+                let access = start + length - 1;
+                return Err(Fault::MemoryOutOfBounds { address: self.program_counter, access });
+            }
+
+            if !write || !self.has_protected() {
+                return Ok(());
+            }
+        }
+
+        for address in start..start + length {
+            let address = self.resolve(address)?;
+
+            if write {
+                self.check_writable(address)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc")]
+    fn has_protected(&self) -> bool {
+        !self.bus.protected.is_empty()
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn has_protected(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "alloc")]
+    fn check_writable(&self, address: usize) -> Result<(), Fault> {
+        if self.bus.protected.iter().any(|region| region.contains(&address)) {
+            Err(Fault::ProtectedWrite { address: self.program_counter, access: address })
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn check_writable(&self, _: usize) -> Result<(), Fault> {
+        Ok(())
+    }
+}
+
+impl Bus for Program<'_> {
+    #[inline]
+    fn read(&mut self, address: usize) -> Result<u8, Fault> {
+        let address = self.resolve(address)?;
+        let value = self.memory[address];
+        self.bus.counters.reads += 1;
+
+        #[cfg(feature = "alloc")]
+        if let Some(hook) = &mut self.bus.hook {
+            return Ok(hook.read(address, value));
+        }
+
+        Ok(value)
+    }
+
+    #[inline]
+    fn write(&mut self, address: usize, value: u8) -> Result<(), Fault> {
+        let address = self.resolve(address)?;
+        self.check_writable(address)?;
+        self.bus.counters.writes += 1;
+
+        #[cfg(feature = "alloc")]
+        let value = match &mut self.bus.hook {
+            Some(hook) => hook.write(address, value),
+            None => Some(value),
+        };
+        #[cfg(not(feature = "alloc"))]
+        let value = Some(value);
+
+        if let Some(value) = value {
+            self.poke(address, value);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ExecutionState;
+
+    fn loaded(rom: &[u8]) -> Program<'static> {
+        let mut program = Program::new();
+        program.load(rom).unwrap();
+        program
+    }
+
+    #[test]
+    fn address_policies() {
+        // I = 0xFFF, then the 3 digits of v0 are stored.
+        let rom = [0x60, 0x7B, 0xAF, 0xFF, 0xF0, 0x33];
+
+        let mut program = loaded(&rom);
+        let fault = Fault::MemoryOutOfBounds { address: 0x204, access: 0x1001 };
+        assert_eq!(program.run_until_stopped(3), ExecutionState::Faulted(fault));
+        assert_eq!(program.memory()[0xFFF], 0);
+
+        for policy in [AddressPolicy::Wrap, AddressPolicy::Mask(0xFFF)] {
+            let mut program = loaded(&rom);
+            program.set_address_policy(policy);
+            program.run_until_stopped(3);
+
+            let memory = program.memory();
+            assert_eq!((memory[0xFFF], memory[0], memory[1]), (1, 2, 3));
+            assert_eq!(program.access_counters(), AccessCounters { reads: 0, writes: 3 });
+        }
+
+        let mut program = loaded(&rom);
+        program.set_address_policy(AddressPolicy::Mask(0x1FFF));
+        let fault = Fault::MemoryOutOfBounds { address: 0x204, access: 0x1000 };
+        assert_eq!(program.run_until_stopped(3), ExecutionState::Faulted(fault));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn protected_regions() {
+        // v1 = 0xAA, I = 0x1FF, then v0 and v1 are stored.
+        let mut program = loaded(&[0x61, 0xAA, 0xA1, 0xFF, 0xF1, 0x55]);
+        program.protect(0..0x200);
+
+        let fault = Fault::ProtectedWrite { address: 0x204, access: 0x1FF };
+        assert_eq!(program.run_until_stopped(3), ExecutionState::Faulted(fault));
+        assert_eq!(program.memory()[0x1FF..0x201], [0, 0x61]);
+        assert_eq!(program.access_counters().writes, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn hooks() {
+        use crate::program::Register;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Default)]
+        struct Log(Vec<(usize, bool)>);
+
+        struct Hook(Rc<RefCell<Log>>);
+
+        impl BusHook for Hook {
+            fn read(&mut self, address: usize, value: u8) -> u8 {
+                self.0.borrow_mut().0.push((address, false));
+                value ^ 0xFF
+            }
+
+            fn write(&mut self, address: usize, _: u8) -> Option<u8> {
+                self.0.borrow_mut().0.push((address, true));
+                None
+            }
+        }
+
+        // I = 0x300, v0 = 0x42, stored then read back.
+        let mut program = loaded(&[0xA3, 0x00, 0x60, 0x42, 0xF0, 0x55, 0xA3, 0x00, 0xF0, 0x65]);
+        let log = Rc::new(RefCell::new(Log::default()));
+        program.set_bus_hook(Box::new(Hook(log.clone())));
+        program.run_until_stopped(5);
+
+        assert_eq!(log.borrow().0, [(0x300, true), (0x300, false)]);
+        assert_eq!(program.memory()[0x300], 0);
+        assert_eq!(program.register(Register::V0), 0xFF);
+    }
+}
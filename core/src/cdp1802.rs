@@ -0,0 +1,438 @@
+//! The RCA CDP1802 microprocessor of the COSMAC VIP.
+//!
+//! The CPU only knows about its registers: memory, I/O ports and the EF flag inputs are provided
+//! by the [`Board`] it is plugged in, see [`vip`](crate::vip) for the VIP itself.
+
+/// Memory and devices wired to the CPU.
+pub trait Board {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    /// Byte put on the bus by the device selected by `INP`, with `port` from 1 to 7.
+    fn input(&mut self, port: u8) -> u8;
+    /// Byte sent by `OUT` to the device selected by `port`, from 1 to 7.
+    fn output(&mut self, port: u8, value: u8);
+    /// State of the `EF1` to `EF4` flag inputs, tested by the branch instructions.
+    fn flag(&mut self, flag: u8) -> bool;
+}
+
+/// Registers of the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cdp1802 {
+    /// Scratchpad registers, any of them can be the program counter or the data pointer.
+    pub r: [u16; 16],
+    /// Accumulator.
+    pub d: u8,
+    /// Carry, set when there is no borrow after subtractions.
+    pub df: bool,
+    /// Index of the program counter register.
+    pub p: u8,
+    /// Index of the data pointer register.
+    pub x: u8,
+    /// X and P saved when taking an interrupt.
+    pub t: u8,
+    /// Interrupt enable.
+    pub ie: bool,
+    /// Output flip-flop, which drives the beeper of the VIP.
+    pub q: bool,
+    /// Whether `IDL` stopped the CPU until the next interrupt or DMA.
+    pub idle: bool,
+}
+
+impl Cdp1802 {
+    /// A CPU right after a reset, executing from address 0 with R0.
+    pub fn new() -> Self {
+        Cdp1802 { r: [0; 16], d: 0, df: false, p: 0, x: 0, t: 0, ie: true, q: false, idle: false }
+    }
+
+    /// Reset the CPU: I, N, Q, X, P and R0 are cleared and interrupts enabled, the other
+    /// registers keeping their values.
+    pub fn reset(&mut self) {
+        self.q = false;
+        self.x = 0;
+        self.p = 0;
+        self.r[0] = 0;
+        self.ie = true;
+        self.idle = false;
+    }
+
+    fn fetch<B: Board>(&mut self, board: &mut B) -> u8 {
+        let p = self.p as usize;
+        let byte = board.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn set_low(&mut self, n: usize, value: u8) {
+        self.r[n] = (self.r[n] & 0xFF00) | value as u16;
+    }
+
+    fn set_high(&mut self, n: usize, value: u8) {
+        self.r[n] = (self.r[n] & 0x00FF) | (value as u16) << 8;
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    /// `a - b`, DF being set when there is no borrow.
+    fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+        let difference = a as i16 - b as i16 - borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+
+    /// Execute one instruction, returning the machine cycles it took: 2, or 3 for long branches
+    /// and skips. An idle CPU spends a cycle waiting.
+    pub fn step<B: Board>(&mut self, board: &mut B) -> u32 {
+        if self.idle {
+            return 1;
+        }
+
+        let opcode = self.fetch(board);
+        let n = (opcode & 0xF) as usize;
+
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = board.read(self.r[n]),
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let p = self.p as usize;
+                let target = board.read(self.r[p]);
+
+                if self.condition(n as u8, board) {
+                    self.set_low(p, target);
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(1);
+                }
+            },
+            0x4 => {
+                self.d = board.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            0x5 => board.write(self.r[n], self.d),
+            0x6 => self.input_output(n as u8, board),
+            0x7 => self.control(n as u8, board),
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.set_low(n, self.d),
+            0xB => self.set_high(n, self.d),
+            0xC => {
+                self.long_branch(n as u8, board);
+                return 3;
+            },
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => self.alu(n as u8, board),
+        }
+
+        2
+    }
+
+    /// Condition of the short branch `3N`, and of the long branch `CN` for N from 0 to 3 and 8
+    /// to B, N above 7 negating the one of N - 8.
+    fn condition<B: Board>(&mut self, n: u8, board: &mut B) -> bool {
+        let condition = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            flag => board.flag(flag - 3),
+        };
+
+        condition != (n >= 8)
+    }
+
+    fn input_output<B: Board>(&mut self, n: u8, board: &mut B) {
+        let x = self.x as usize;
+
+        match n {
+            0 => self.r[x] = self.r[x].wrapping_add(1),
+            1..=7 => {
+                let value = board.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                board.output(n, value);
+            },
+            // 68 selects no device on the 1802.
+            8 => {},
+            _ => {
+                self.d = board.input(n - 8);
+                board.write(self.r[x], self.d);
+            },
+        }
+    }
+
+    fn control<B: Board>(&mut self, n: u8, board: &mut B) {
+        let x = self.x as usize;
+
+        match n {
+            0 | 1 => {
+                let value = board.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = n == 0;
+            },
+            2 => {
+                self.d = board.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            },
+            3 => {
+                board.write(self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            },
+            4 => {
+                let value = board.read(self.rx());
+                self.add(value, self.d, self.df);
+            },
+            5 => {
+                let value = board.read(self.rx());
+                self.subtract(value, self.d, !self.df);
+            },
+            6 => {
+                let carry = self.df;
+                self.df = self.d & 1 == 1;
+                self.d = self.d >> 1 | (carry as u8) << 7;
+            },
+            7 => {
+                let value = board.read(self.rx());
+                self.subtract(self.d, value, !self.df);
+            },
+            8 => board.write(self.rx(), self.t),
+            9 => {
+                self.t = self.x << 4 | self.p;
+                board.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            },
+            0xA => self.q = false,
+            0xB => self.q = true,
+            0xC => {
+                let value = self.fetch(board);
+                self.add(value, self.d, self.df);
+            },
+            0xD => {
+                let value = self.fetch(board);
+                self.subtract(value, self.d, !self.df);
+            },
+            0xE => {
+                let carry = self.df;
+                self.df = self.d & 0x80 != 0;
+                self.d = self.d << 1 | carry as u8;
+            },
+            _ => {
+                let value = self.fetch(board);
+                self.subtract(self.d, value, !self.df);
+            },
+        }
+    }
+
+    fn long_branch<B: Board>(&mut self, n: u8, board: &mut B) {
+        let p = self.p as usize;
+
+        match n {
+            // Long skips, C4 being NOP.
+            0x4..=0x7 | 0xC..=0xF => {
+                let skip = match n {
+                    0x4 => false,
+                    0x5 => !self.q,
+                    0x6 => self.d != 0,
+                    0x7 => !self.df,
+                    0xC => self.ie,
+                    0xD => self.q,
+                    0xE => self.d == 0,
+                    _ => self.df,
+                };
+
+                if skip {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            },
+            _ => {
+                let high = board.read(self.r[p]);
+                let low = board.read(self.r[p].wrapping_add(1));
+
+                if self.condition(n, board) {
+                    self.r[p] = u16::from_be_bytes([high, low]);
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(2);
+                }
+            },
+        }
+    }
+
+    /// `FN`: operations on M(R(X)) for N up to 7, on the immediate byte otherwise.
+    fn alu<B: Board>(&mut self, n: u8, board: &mut B) {
+        let operand = match n {
+            0x6 | 0xE => 0,
+            0x0..=0x7 => board.read(self.rx()),
+            _ => self.fetch(board),
+        };
+
+        match n & 0x7 {
+            0 => self.d = operand,
+            1 => self.d |= operand,
+            2 => self.d &= operand,
+            3 => self.d ^= operand,
+            4 => self.add(operand, self.d, false),
+            5 => self.subtract(operand, self.d, false),
+            6 if n == 0x6 => {
+                self.df = self.d & 1 == 1;
+                self.d >>= 1;
+            },
+            6 => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            },
+            _ => self.subtract(self.d, operand, false),
+        }
+    }
+
+    /// Take an interrupt if they are enabled, saving X and P in T and running R1 with R2 as data
+    /// pointer. Returns whether the interrupt was taken.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.ie {
+            return false;
+        }
+
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        true
+    }
+
+    /// A DMA output cycle: the byte at R0 is sent to the device requesting it, and R0 moves to
+    /// the next one.
+    pub fn dma_out<B: Board>(&mut self, board: &mut B) -> u8 {
+        let value = board.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Cdp1802::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ram {
+        memory: [u8; 0x100],
+        outputs: Vec<(u8, u8)>,
+        flags: u8,
+    }
+
+    impl Ram {
+        fn new(program: &[u8]) -> Self {
+            let mut memory = [0; 0x100];
+            memory[..program.len()].copy_from_slice(program);
+
+            Ram { memory, outputs: Vec::new(), flags: 0 }
+        }
+    }
+
+    impl Board for Ram {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize & 0xFF]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize & 0xFF] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x10 + port
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn flag(&mut self, flag: u8) -> bool {
+            self.flags & (1 << (flag - 1)) != 0
+        }
+    }
+
+    fn run(cpu: &mut Cdp1802, ram: &mut Ram, instructions: usize) -> u32 {
+        (0..instructions).map(|_| cpu.step(ram)).sum()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut ram = Ram::new(&[
+            0xF8, 0xF0, // LDI F0
+            0xFC, 0x20, // ADI 20: D = 10, DF
+            0x7C, 0x01, // ADCI 01: D = 12
+            0xFF, 0x13, // SMI 13: D = FF, borrow
+            0x7E,       // SHLC: D = FE, DF
+            0xF6,       // SHR: D = 7F
+        ]);
+        let mut cpu = Cdp1802::new();
+
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!((cpu.d, cpu.df), (0x12, false));
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!((cpu.d, cpu.df), (0xFF, false));
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!((cpu.d, cpu.df), (0xFE, true));
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!((cpu.d, cpu.df), (0x7F, false));
+    }
+
+    #[test]
+    fn registers_and_branches() {
+        let mut ram = Ram::new(&[
+            0xF8, 0x80, 0xB3, // R3.1 = 80
+            0xF8, 0x40, 0xA3, // R3.0 = 40
+            0xE3,             // SEX 3
+            0x36, 0x0C,       // B3 0C, not taken
+            0x3E, 0x0C,       // BN3 0C, taken
+            0x00,
+            0x64,             // OUT 4, M(R3) = M(40)
+            0xC0, 0x00, 0x20, // LBR 0020
+        ]);
+        ram.memory[0x40] = 0x42;
+        let mut cpu = Cdp1802::new();
+
+        assert_eq!(run(&mut cpu, &mut ram, 9), 9 * 2 + 1);
+        assert_eq!(cpu.r[3], 0x8041);
+        assert_eq!(ram.outputs, vec![(4, 0x42)]);
+        assert_eq!(cpu.r[0], 0x20);
+    }
+
+    #[test]
+    fn interrupts_and_dma() {
+        let mut ram = Ram::new(&[0x00]);
+        ram.memory[0x80] = 0xAA;
+
+        let mut cpu = Cdp1802::new();
+        cpu.p = 3;
+        cpu.r[0] = 0x80;
+        cpu.step(&mut ram);
+        assert!(cpu.idle);
+
+        assert_eq!(cpu.dma_out(&mut ram), 0xAA);
+        assert_eq!(cpu.r[0], 0x81);
+        assert!(!cpu.idle);
+
+        cpu.x = 5;
+        assert!(cpu.interrupt());
+        assert_eq!((cpu.t, cpu.p, cpu.x, cpu.ie), (0x53, 1, 2, false));
+        assert!(!cpu.interrupt());
+    }
+}
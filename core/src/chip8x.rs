@@ -0,0 +1,196 @@
+//! The colour board of the COSMAC VIP, as driven by the CHIP-8X interpreter.
+//!
+//! The board doesn't change the monochrome display: it adds a colour RAM giving the foreground
+//! colour of every 8 pixels wide and 1 pixel high zone of the screen, and a background colour
+//! shared by the whole screen. `BXY0` and `BXYN` write the colour RAM, `02A0` cycles the
+//! background.
+
+#[cfg(feature = "alloc")]
+use crate::display::Display;
+use crate::platform::Platform;
+use crate::program::{ Cursor, Program };
+use crate::render::Color;
+use crate::state::Fault;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Width in pixels of the zones sharing a foreground colour.
+pub const ZONE_WIDTH: usize = 8;
+/// Height in pixels of the zones set by `BXY0`, `BXYN` sets single rows.
+pub const ZONE_HEIGHT: usize = 4;
+
+const COLUMNS: usize = 64 / ZONE_WIDTH;
+const ROWS: usize = 32;
+
+/// Foreground colours, the bits of their index enabling the red, blue and green guns.
+pub const FOREGROUNDS: [Color; 8] = [
+    Color::BLACK,
+    Color::from_hex(0xFF0000),
+    Color::from_hex(0x0000FF),
+    Color::from_hex(0xFF00FF),
+    Color::from_hex(0x00FF00),
+    Color::from_hex(0xFFFF00),
+    Color::from_hex(0x00FFFF),
+    Color::WHITE,
+];
+
+/// Background colours, in the order `02A0` cycles through them.
+pub const BACKGROUNDS: [Color; 4] = [
+    Color::from_hex(0x000080),
+    Color::BLACK,
+    Color::from_hex(0x008000),
+    Color::from_hex(0x800000),
+];
+
+/// Colour RAM of the board, layered over the monochrome screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColorLayer {
+    background: usize,
+    foregrounds: [[u8; COLUMNS]; ROWS],
+}
+
+impl ColorLayer {
+    /// Red pixels on a dark blue background, as the interpreter starts.
+    pub fn new() -> Self {
+        ColorLayer { background: 0, foregrounds: [[1; COLUMNS]; ROWS] }
+    }
+
+    pub fn background(&self) -> Color {
+        BACKGROUNDS[self.background]
+    }
+
+    /// Switch to the next background colour.
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+    }
+
+    /// Foreground colour of the pixel at `x` and `y`.
+    pub fn foreground(&self, x: usize, y: usize) -> Color {
+        FOREGROUNDS[self.foregrounds[y % ROWS][x / ZONE_WIDTH % COLUMNS] as usize]
+    }
+
+    /// Set the foreground colour of `rows` rows of the zone column `column`, from the pixel row
+    /// `top`, wrapping around the screen.
+    pub fn set_foreground(&mut self, column: usize, top: usize, rows: usize, color: u8) {
+        for row in top..top + rows {
+            self.foregrounds[row % ROWS][column % COLUMNS] = color & 0x7;
+        }
+    }
+
+    /// RGBA pixels of `screen` coloured by the layer.
+    #[cfg(feature = "alloc")]
+    pub fn to_rgba(&self, screen: &Display) -> Vec<u8> {
+        let (width, height) = (screen.width(), screen.height());
+        let mut pixels = Vec::with_capacity(width * height * 4);
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = match screen.get(x, y) {
+                    true => self.foreground(x, y),
+                    false => self.background(),
+                };
+                pixels.extend_from_slice(&color.to_rgba());
+            }
+        }
+
+        pixels
+    }
+}
+
+impl Default for ColorLayer {
+    fn default() -> Self {
+        ColorLayer::new()
+    }
+}
+
+/// Fault unless `program` runs CHIP-8X, for the instructions only this interpreter knows.
+pub(crate) fn only(program: &Program, opcode: u16) -> Option<Cursor> {
+    if program.platform() == Some(Platform::Chip8X) {
+        None
+    } else {
+        Some(Cursor::Fault(Fault::InvalidInstruction { address: program.program_counter, opcode }))
+    }
+}
+
+/// Run `BXYN`, the colour being V(X + 1).
+///
+/// With N = 0, the lowest nibbles of VX and VY give the first zone column and the first 4 rows
+/// high zone row, their highest nibbles how many more columns and rows are set. Otherwise, N
+/// rows are set from the pixel at VX and VY.
+pub(crate) fn set_colors(program: &mut Program, x: usize, y: usize, n: usize) -> Cursor {
+    let (vx, vy) = (program.v[x] as usize, program.v[y] as usize);
+    let color = program.v[(x + 1) & 0xF];
+
+    if n == 0 {
+        for column in (vx & 0xF)..=(vx & 0xF) + (vx >> 4) {
+            let top = (vy & 0xF) * ZONE_HEIGHT;
+            let rows = ((vy >> 4) + 1) * ZONE_HEIGHT;
+
+            program.colors.set_foreground(column, top, rows, color);
+        }
+    } else {
+        program.colors.set_foreground(vx / ZONE_WIDTH, vy, n, color);
+    }
+
+    Cursor::Next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmulatorConfig;
+    use crate::keypad::Key;
+    use crate::program::Register;
+    use crate::state::ExecutionState;
+
+    fn chip8x(rom: &[u8]) -> Program<'static> {
+        let mut program = EmulatorConfig::new().platform(Platform::Chip8X).build().unwrap();
+        program.load(rom).unwrap();
+        program
+    }
+
+    #[test]
+    fn colour_zones() {
+        let mut program = chip8x(&[
+            0x60, 0x11, // v0 = 0x11, zone columns 1 and 2
+            0x61, 0x04, // v1 = 4, green
+            0x62, 0x01, // v2 = 1, second zone row
+            0xB0, 0x20, // colour zones
+            0x63, 0x00, // v3 = 0
+            0x64, 0x02, // v4 = 2, blue
+            0xB3, 0x32, // colour 2 rows at (0, 0)
+            0x02, 0xA0, // next background
+        ]);
+
+        for _ in 0..8 {
+            program.run();
+        }
+
+        let green = FOREGROUNDS[4];
+        assert_eq!(program.colors.foreground(8, 4), green);
+        assert_eq!(program.colors.foreground(23, 7), green);
+        assert_eq!(program.colors.foreground(24, 4), FOREGROUNDS[1]);
+        assert_eq!(program.colors.foreground(7, 3), FOREGROUNDS[1]);
+        assert_eq!(program.colors.foreground(0, 1), FOREGROUNDS[2]);
+        assert_eq!(program.colors.background(), Color::BLACK);
+    }
+
+    #[test]
+    fn packed_addition_and_second_keypad() {
+        let mut program = chip8x(&[0x60, 0x35, 0x61, 0x4B, 0x50, 0x11, 0xE2, 0xF2]);
+        program.second_keydown(Key::Zero);
+
+        for _ in 0..4 {
+            program.run();
+        }
+
+        assert_eq!(program.register(Register::V0), 0x70);
+        assert_eq!(program.program_counter, 0x30A);
+
+        let mut program = Program::new();
+        program.load(&[0x50, 0x11]).unwrap();
+        let fault = Fault::InvalidInstruction { address: 0x200, opcode: 0x5011 };
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+    }
+}
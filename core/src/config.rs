@@ -0,0 +1,449 @@
+//! Machine parameters of the interpreter.
+//!
+//! ```
+//! use chip8_core::config::EmulatorConfig;
+//!
+//! // An ETI-660, which loads programs at 0x600, in memory provided by the caller.
+//! let mut memory = [0; 0x1000];
+//! let program = EmulatorConfig::new()
+//!     .start_address(0x600)
+//!     .build_in(&mut memory)
+//!     .unwrap();
+//!
+//! assert_eq!(program.program_counter, 0x600);
+//! ```
+//!
+//! With the `alloc` feature, [`build`](EmulatorConfig::build) allocates the memory instead.
+
+use crate::display::{ MAX_HEIGHT, MAX_WIDTH };
+use crate::font::{ Font, LargeFont };
+use crate::layout::{ self, MemoryLayout };
+use crate::platform::Platform;
+use crate::program::Program;
+use crate::quirks::Quirks;
+#[cfg(feature = "alloc")]
+use crate::recompiler::Engine;
+use crate::rom::MemoryFill;
+use crate::timing::Timing;
+
+use core::error::Error;
+use core::fmt;
+use core::ops::Range;
+
+/// Largest addressable memory, 64 KiB like XO-CHIP.
+pub const MAX_MEMORY_SIZE: usize = 0x10000;
+/// Deepest call stack supported.
+pub const MAX_STACK_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmulatorConfig {
+    pub(crate) memory_size: usize,
+    pub(crate) memory_fill: MemoryFill,
+    pub(crate) start_address: u16,
+    pub(crate) stack_depth: usize,
+    pub(crate) font: Font,
+    pub(crate) font_address: u16,
+    pub(crate) large_font: Option<LargeFont>,
+    pub(crate) large_font_address: u16,
+    pub(crate) display_width: usize,
+    pub(crate) display_height: usize,
+    pub(crate) instructions_per_frame: usize,
+    pub(crate) timing: Timing,
+    pub(crate) memory_layout: MemoryLayout,
+    #[cfg(feature = "alloc")]
+    pub(crate) decode_cache: bool,
+    #[cfg(feature = "alloc")]
+    pub(crate) engine: Engine,
+    pub(crate) quirks: Quirks,
+    pub(crate) platform: Option<Platform>,
+}
+
+impl EmulatorConfig {
+    /// The usual CHIP-8 machine: 4 KiB of memory, programs loaded at 0x200, 16 stack entries,
+    /// the Octo font at address 0 followed by the SCHIP large font at 0x50, a 64x32 display and
+    /// 10 instructions per frame.
+    pub fn new() -> Self {
+        EmulatorConfig {
+            memory_size: 0x1000,
+            memory_fill: MemoryFill::Zero,
+            start_address: 0x200,
+            stack_depth: 16,
+            font: Font::Octo,
+            font_address: 0,
+            large_font: Some(LargeFont::Schip),
+            large_font_address: 0x50,
+            display_width: 64,
+            display_height: 32,
+            instructions_per_frame: 10,
+            timing: Timing::Instructions,
+            memory_layout: MemoryLayout::Separate,
+            #[cfg(feature = "alloc")]
+            decode_cache: true,
+            #[cfg(feature = "alloc")]
+            engine: Engine::Interpreter,
+            quirks: Quirks::default(),
+            platform: None,
+        }
+    }
+
+    /// The original COSMAC VIP interpreter, which only had room for 12 stack entries.
+    pub fn vip() -> Self {
+        EmulatorConfig::new().stack_depth(12).font(Font::Vip).large_font(None)
+    }
+
+    /// The ETI-660, loading programs at 0x600.
+    pub fn eti660() -> Self {
+        EmulatorConfig::new().start_address(0x600).font(Font::Eti660).large_font(None)
+    }
+
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    /// Content of the memory outside of the fonts and the ROM.
+    pub fn memory_fill(mut self, fill: MemoryFill) -> Self {
+        self.memory_fill = fill;
+        self
+    }
+
+    /// Address where programs are loaded and execution starts.
+    pub fn start_address(mut self, address: u16) -> Self {
+        self.start_address = address;
+        self
+    }
+
+    pub fn stack_depth(mut self, depth: usize) -> Self {
+        self.stack_depth = depth;
+        self
+    }
+
+    /// Glyphs of the hexadecimal digits used by `FX29`.
+    pub fn font(mut self, font: Font) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// Address where the font is copied.
+    pub fn font_address(mut self, address: u16) -> Self {
+        self.font_address = address;
+        self
+    }
+
+    /// Glyphs used by the SCHIP `FX30`, which is invalid without them.
+    pub fn large_font(mut self, font: Option<LargeFont>) -> Self {
+        self.large_font = font;
+        self
+    }
+
+    /// Address where the large font is copied.
+    pub fn large_font_address(mut self, address: u16) -> Self {
+        self.large_font_address = address;
+        self
+    }
+
+    pub fn display_size(mut self, width: usize, height: usize) -> Self {
+        self.display_width = width;
+        self.display_height = height;
+        self
+    }
+
+    /// Instructions executed by [`Program::run_frame`](crate::program::Program::run_frame).
+    pub fn instructions_per_frame(mut self, count: usize) -> Self {
+        self.instructions_per_frame = count;
+        self
+    }
+
+    /// How [`Program::run_frame`](crate::program::Program::run_frame) decides when a frame is
+    /// over, `instructions_per_frame` only applying to [`Timing::Instructions`].
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Whether the registers, stack and display are mapped in memory.
+    pub fn memory_layout(mut self, layout: MemoryLayout) -> Self {
+        self.memory_layout = layout;
+        self
+    }
+
+    /// Whether decoded instructions are kept by address instead of being decoded again every
+    /// time they are executed. Writes to memory invalidate them, so this never changes the
+    /// behaviour of programs, only their speed. The cache is allocated with the program.
+    #[cfg(feature = "alloc")]
+    pub fn decode_cache(mut self, enabled: bool) -> Self {
+        self.decode_cache = enabled;
+        self
+    }
+
+    /// How instructions are executed, see [`recompiler`](crate::recompiler).
+    #[cfg(feature = "alloc")]
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Emulate `platform`, using its quirks, display size and start address.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        let (width, height) = platform.display_size();
+
+        self.platform = Some(platform);
+        self.quirks(platform.quirks())
+            .display_size(width, height)
+            .start_address(platform.start_address())
+    }
+
+    pub fn get_memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    pub fn get_memory_fill(&self) -> MemoryFill {
+        self.memory_fill
+    }
+
+    pub fn get_start_address(&self) -> u16 {
+        self.start_address
+    }
+
+    pub fn get_stack_depth(&self) -> usize {
+        self.stack_depth
+    }
+
+    pub fn get_font(&self) -> Font {
+        self.font
+    }
+
+    pub fn get_font_address(&self) -> u16 {
+        self.font_address
+    }
+
+    pub fn get_large_font(&self) -> Option<LargeFont> {
+        self.large_font
+    }
+
+    pub fn get_large_font_address(&self) -> u16 {
+        self.large_font_address
+    }
+
+    pub fn get_display_size(&self) -> (usize, usize) {
+        (self.display_width, self.display_height)
+    }
+
+    pub fn get_instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn get_timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn get_memory_layout(&self) -> MemoryLayout {
+        self.memory_layout
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn get_decode_cache(&self) -> bool {
+        self.decode_cache
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn get_engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// The emulated platform, `None` meaning the historical behaviour of this interpreter.
+    pub fn get_platform(&self) -> Option<Platform> {
+        self.platform
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0x200..=MAX_MEMORY_SIZE).contains(&self.memory_size) {
+            return Err(ConfigError::MemorySize(self.memory_size));
+        }
+
+        // There must at least be room for one instruction.
+        if self.start_address as usize + 2 > self.memory_size {
+            return Err(ConfigError::StartAddress(self.start_address));
+        }
+
+        if !(1..=MAX_STACK_DEPTH).contains(&self.stack_depth) {
+            return Err(ConfigError::StackDepth(self.stack_depth));
+        }
+
+        // Fonts can't be overwritten by the ROM, loaded from the start address.
+        let program = self.start_address as usize..self.memory_size;
+        let overlap = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+
+        let font = self.font_address as usize..self.font_address as usize + self.font.size();
+        if font.end > self.memory_size || overlap(&font, &program) {
+            return Err(ConfigError::FontAddress(self.font_address));
+        }
+
+        if let Some(large_font) = self.large_font {
+            let address = self.large_font_address as usize;
+            let large = address..address + large_font.size();
+
+            if large.end > self.memory_size || overlap(&large, &program) || overlap(&large, &font) {
+                return Err(ConfigError::FontAddress(self.large_font_address));
+            }
+        }
+
+        let (width, height) = (self.display_width, self.display_height);
+        if !(1..=MAX_WIDTH).contains(&width) || !(1..=MAX_HEIGHT).contains(&height) {
+            return Err(ConfigError::DisplaySize(width, height));
+        }
+
+        if self.instructions_per_frame == 0 {
+            return Err(ConfigError::InstructionsPerFrame);
+        }
+
+        if self.memory_layout == MemoryLayout::Vip
+            && (self.memory_size < layout::END
+                || self.stack_depth > layout::STACK_ENTRIES
+                || (width, height) != (64, 32))
+        {
+            return Err(ConfigError::MemoryLayout);
+        }
+
+        Ok(())
+    }
+
+    /// Build a program, allocating its memory.
+    #[cfg(any(feature = "alloc", test))]
+    pub fn build(self) -> Result<Program<'static>, ConfigError> {
+        Program::with_config(self)
+    }
+
+    /// Build a program running in `memory`, see [`Program::with_memory`].
+    pub fn build_in(self, memory: &mut [u8]) -> Result<Program<'_>, ConfigError> {
+        Program::with_memory(self, memory)
+    }
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The memory must be between 512 bytes and 64 KiB.
+    MemorySize(usize),
+    /// The memory lent to a program, of the given size, must hold the configured memory size.
+    MemoryBuffer(usize),
+    /// Programs must start inside the memory.
+    StartAddress(u16),
+    /// The stack must have between 1 and 32 entries.
+    StackDepth(usize),
+    /// A font must fit in memory, without overlapping the other font or the start of the
+    /// program.
+    FontAddress(u16),
+    /// The display can't be larger than 128x64.
+    DisplaySize(usize, usize),
+    /// At least one instruction must be executed per frame.
+    InstructionsPerFrame,
+    /// The VIP memory layout needs 4 KiB of memory, at most 24 stack entries and a 64x32
+    /// display.
+    MemoryLayout,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MemorySize(size) => {
+                write!(f, "memory size {:#x} isn't between 0x200 and {:#x}", size, MAX_MEMORY_SIZE)
+            },
+            ConfigError::MemoryBuffer(size) => {
+                write!(f, "memory buffer of {:#x} bytes is smaller than the memory size", size)
+            },
+            ConfigError::StartAddress(address) => {
+                write!(f, "start address {:#x} is outside of the memory", address)
+            },
+            ConfigError::StackDepth(depth) => {
+                write!(f, "stack depth {} isn't between 1 and {}", depth, MAX_STACK_DEPTH)
+            },
+            ConfigError::FontAddress(address) => {
+                write!(f, "font at {:#x} doesn't fit in memory alongside the program", address)
+            },
+            ConfigError::DisplaySize(width, height) => {
+                write!(f, "display size {}x{} exceeds {}x{}", width, height, MAX_WIDTH, MAX_HEIGHT)
+            },
+            ConfigError::InstructionsPerFrame => {
+                write!(f, "at least one instruction must be executed per frame")
+            },
+            ConfigError::MemoryLayout => {
+                write!(f, "the VIP memory layout needs 4 KiB of memory, at most {} stack entries \
+                    and a 64x32 display", layout::STACK_ENTRIES)
+            },
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert_eq!(EmulatorConfig::new().validate(), Ok(()));
+        assert_eq!(
+            EmulatorConfig::new().memory_size(0x100).validate(),
+            Err(ConfigError::MemorySize(0x100))
+        );
+        assert_eq!(
+            EmulatorConfig::new().start_address(0xFFF).validate(),
+            Err(ConfigError::StartAddress(0xFFF))
+        );
+        assert_eq!(
+            EmulatorConfig::new().font_address(0x1C0).validate(),
+            Err(ConfigError::FontAddress(0x1C0))
+        );
+        assert_eq!(
+            EmulatorConfig::new().font_address(0x300).validate(),
+            Err(ConfigError::FontAddress(0x300))
+        );
+        assert_eq!(
+            EmulatorConfig::new().display_size(256, 192).validate(),
+            Err(ConfigError::DisplaySize(256, 192))
+        );
+        assert_eq!(
+            EmulatorConfig::new().large_font_address(0x20).validate(),
+            Err(ConfigError::FontAddress(0x20))
+        );
+        assert_eq!(EmulatorConfig::vip().stack_depth(0).validate(), Err(ConfigError::StackDepth(0)));
+
+        let vip = EmulatorConfig::vip().memory_layout(MemoryLayout::Vip);
+        assert_eq!(vip.validate(), Ok(()));
+        assert_eq!(vip.memory_size(0x800).validate(), Err(ConfigError::MemoryLayout));
+        assert_eq!(vip.stack_depth(32).validate(), Err(ConfigError::MemoryLayout));
+        assert_eq!(vip.display_size(128, 64).validate(), Err(ConfigError::MemoryLayout));
+    }
+
+    #[test]
+    fn build_applies_parameters() {
+        let program = EmulatorConfig::new()
+            .font(Font::Vip)
+            .font_address(0x50)
+            .large_font_address(0xA0)
+            .display_size(64, 64)
+            .build()
+            .unwrap();
+
+        assert_eq!(program.memory()[0x50..0x55], Font::Vip.glyphs()[0]);
+        assert_eq!(program.memory()[0xA0..0xAA], LargeFont::Schip.glyphs()[0]);
+        assert_eq!(program.memory()[0x00], 0);
+        assert_eq!((program.screen.width(), program.screen.height()), (64, 64));
+    }
+}
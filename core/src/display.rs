@@ -0,0 +1,124 @@
+//! Monochrome display of configurable size.
+
+use crate::render::Frame;
+
+/// Widest display supported, the SCHIP high resolution mode.
+pub const MAX_WIDTH: usize = 128;
+/// Tallest display supported.
+pub const MAX_HEIGHT: usize = 64;
+
+/// A monochrome display of at most [`MAX_WIDTH`] by [`MAX_HEIGHT`] pixels.
+///
+/// Each row is stored as a bit set, the leftmost pixel being the most significant bit, so the
+/// display never allocates whatever its size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    rows: [u128; MAX_HEIGHT],
+}
+
+impl Display {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!((1..=MAX_WIDTH).contains(&width), "invalid display width {}", width);
+        assert!((1..=MAX_HEIGHT).contains(&height), "invalid display height {}", height);
+
+        Display { width, height, rows: [0; MAX_HEIGHT] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn mask(x: usize) -> u128 {
+        1 << (MAX_WIDTH - 1 - x)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & Display::mask(x) != 0
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: bool) {
+        if value {
+            self.rows[y] |= Display::mask(x);
+        } else {
+            self.rows[y] &= !Display::mask(x);
+        }
+    }
+
+    /// Flip the pixel at (`x`, `y`), returning whether it was lit, i.e. whether it collided.
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let lit = self.get(x, y);
+        self.rows[y] ^= Display::mask(x);
+
+        lit
+    }
+
+    /// The 8 pixels of row `y` starting at `x = 8 * column`, the leftmost being the most
+    /// significant bit.
+    pub(crate) fn byte(&self, column: usize, y: usize) -> u8 {
+        (self.rows[y] >> (MAX_WIDTH - 8 - 8 * column)) as u8
+    }
+
+    pub(crate) fn set_byte(&mut self, column: usize, y: usize, value: u8) {
+        let shift = MAX_WIDTH - 8 - 8 * column;
+        self.rows[y] = self.rows[y] & !(0xFF << shift) | (value as u128) << shift;
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; MAX_HEIGHT];
+    }
+
+    /// Change the size of the display, clearing it.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Display::new(width, height);
+    }
+
+    /// Rows of pixels, from top to bottom, each from left to right.
+    pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = bool> + '_> + '_ {
+        (0..self.height).map(move |y| (0..self.width).map(move |x| self.get(x, y)))
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new(64, 32)
+    }
+}
+
+impl Frame for Display {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.get(x, y) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_reports_collisions() {
+        let mut display = Display::new(128, 64);
+
+        assert!(!display.toggle(127, 63));
+        assert!(display.get(127, 63));
+        assert!(display.toggle(127, 63));
+        assert!(!display.get(127, 63));
+
+        display.set(0, 0, true);
+        display.clear();
+        assert!(!display.get(0, 0));
+    }
+}
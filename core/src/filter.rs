@@ -0,0 +1,162 @@
+//! Display filters smoothing the flicker of XOR-drawn sprites.
+//!
+//! Games move sprites by erasing them (drawing them a second time) and drawing them again at
+//! their new position, so a sprite is often missing from the screen when a frame is presented.
+//! A [`PhosphorFilter`] keeps track of the previous frames to hide those gaps, somewhat like the
+//! phosphor of a CRT did.
+
+use crate::render::{ Frame, Intensities };
+
+use alloc::{ vec, vec::Vec };
+use core::error::Error;
+use core::fmt;
+
+/// How long a pixel stays visible after being turned off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Persistence {
+    /// No filtering, the intensities follow the screen.
+    #[default]
+    None,
+    /// Pixels turned off fade out, losing `amount` of intensity (out of 255) on each frame.
+    Decay { amount: u8 },
+    /// A pixel is lit if it was lit on any of the last `frames` frames (from 1 to 32).
+    Blend { frames: u8 },
+}
+
+/// Smoothed intensities of the screen, updated once per displayed frame.
+#[derive(Clone, Debug, Default)]
+pub struct PhosphorFilter {
+    persistence: Persistence,
+    intensities: Intensities,
+    history: Vec<u32>,
+}
+
+impl PhosphorFilter {
+    pub fn new(persistence: Persistence) -> Result<Self, FilterError> {
+        if let Persistence::Blend { frames } = persistence {
+            if !(1..=32).contains(&frames) {
+                return Err(FilterError::BlendFrames(frames));
+            }
+        }
+
+        Ok(PhosphorFilter {
+            persistence,
+            intensities: Intensities::default(),
+            history: Vec::new(),
+        })
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    /// Change the persistence, e.g. when another game is loaded, and forget previous frames.
+    /// The filter is left as it was if `persistence` is invalid.
+    pub fn set_persistence(&mut self, persistence: Persistence) -> Result<(), FilterError> {
+        *self = PhosphorFilter::new(persistence)?;
+        Ok(())
+    }
+
+    /// Forget every previous frame.
+    pub fn reset(&mut self) {
+        self.intensities = Intensities::default();
+        self.history.clear();
+    }
+
+    /// Last computed intensities.
+    pub fn intensities(&self) -> &Intensities {
+        &self.intensities
+    }
+
+    /// Feed a new frame to the filter. It should be called once per displayed frame (usually at
+    /// 60 Hz) rather than after every instruction.
+    pub fn update<F: Frame + ?Sized>(&mut self, frame: &F) -> &Intensities {
+        let (width, height) = (frame.width(), frame.height());
+
+        if self.intensities.dimensions() != (width, height) {
+            self.intensities = Intensities::new(width, height);
+            self.history = vec![0; width * height];
+        }
+
+        let persistence = self.persistence;
+        let values = self.intensities.values_mut();
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let lit = frame.pixel(x, y) != 0;
+
+                values[index] = match persistence {
+                    Persistence::None => if lit { 0xFF } else { 0 },
+                    Persistence::Decay { amount } => {
+                        if lit { 0xFF } else { values[index].saturating_sub(amount) }
+                    },
+                    Persistence::Blend { frames } => {
+                        let mask = u32::MAX >> (32 - frames as u32);
+                        let history = &mut self.history[index];
+
+                        *history = ((*history << 1) | lit as u32) & mask;
+                        if *history != 0 { 0xFF } else { 0 }
+                    }
+                };
+            }
+        }
+
+        &self.intensities
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// Blending must use between 1 and 32 frames.
+    BlendFrames(u8),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::BlendFrames(frames) => {
+                write!(f, "blend must use between 1 and 32 frames, not {}", frames)
+            },
+        }
+    }
+}
+
+impl Error for FilterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay_fades_out() {
+        let mut filter = PhosphorFilter::new(Persistence::Decay { amount: 0x80 }).unwrap();
+
+        assert_eq!(filter.update(&[[true]]).value(0, 0), 0xFF);
+        assert_eq!(filter.update(&[[false]]).value(0, 0), 0x7F);
+        assert_eq!(filter.update(&[[false]]).value(0, 0), 0x00);
+        assert_eq!(filter.update(&[[true]]).value(0, 0), 0xFF);
+    }
+
+    #[test]
+    fn blend_keeps_last_frames() {
+        let mut filter = PhosphorFilter::new(Persistence::Blend { frames: 2 }).unwrap();
+
+        assert_eq!(filter.update(&[[true]]).value(0, 0), 0xFF);
+        assert_eq!(filter.update(&[[false]]).value(0, 0), 0xFF);
+        assert_eq!(filter.update(&[[false]]).value(0, 0), 0x00);
+    }
+
+    #[test]
+    fn blend_frames_are_checked() {
+        for frames in [0, 33] {
+            let persistence = Persistence::Blend { frames };
+            let error = PhosphorFilter::new(persistence).err();
+            assert_eq!(error, Some(FilterError::BlendFrames(frames)));
+        }
+
+        let mut filter = PhosphorFilter::new(Persistence::Blend { frames: 32 }).unwrap();
+        assert!(filter.set_persistence(Persistence::Blend { frames: 0 }).is_err());
+        assert_eq!(filter.persistence(), Persistence::Blend { frames: 32 });
+    }
+}
@@ -0,0 +1,224 @@
+//! Hexadecimal digit fonts of the various interpreters.
+//!
+//! Glyphs are copied in memory when a [`Program`](crate::program::Program) is built, at the
+//! addresses chosen in its [`EmulatorConfig`](crate::config::EmulatorConfig). `FX29` points `I`
+//! at the small glyph of a digit and the SCHIP `FX30` at its large glyph.
+
+use crate::program::SPRITES;
+
+const VIP: [[u8; 5]; 16] = [
+    [0xF0, 0x90, 0x90, 0x90, 0xF0],
+    [0x60, 0x20, 0x20, 0x20, 0x70],
+    [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x10, 0x70, 0x10, 0xF0],
+    [0xA0, 0xA0, 0xF0, 0x20, 0x20],
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x10, 0x10, 0x10, 0x10],
+    [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+    [0xF0, 0x90, 0xF0, 0x90, 0x90],
+    [0xF0, 0x50, 0x70, 0x50, 0xF0],
+    [0xF0, 0x80, 0x80, 0x80, 0xF0],
+    [0xF0, 0x50, 0x50, 0x50, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+    [0xF0, 0x80, 0xF0, 0x80, 0x80]
+];
+
+const DREAM_6800: [[u8; 5]; 16] = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+    [0x80, 0xA0, 0xA0, 0xE0, 0x20],
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+    [0xC0, 0xA0, 0xE0, 0xA0, 0xC0],
+    [0xE0, 0x80, 0x80, 0x80, 0xE0],
+    [0xC0, 0xA0, 0xA0, 0xA0, 0xC0],
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x80, 0xC0, 0x80, 0x80]
+];
+
+const ETI_660: [[u8; 5]; 16] = [
+    [0xE0, 0xA0, 0xA0, 0xA0, 0xE0],
+    [0x20, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0x20, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x20, 0xE0, 0x20, 0xE0],
+    [0xA0, 0xA0, 0xE0, 0x20, 0x20],
+    [0xE0, 0x80, 0xE0, 0x20, 0xE0],
+    [0xE0, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x20, 0x20, 0x20, 0x20],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0x20, 0xE0],
+    [0xE0, 0xA0, 0xE0, 0xA0, 0xA0],
+    [0x80, 0x80, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x80, 0x80, 0x80, 0xE0],
+    [0x20, 0x20, 0xE0, 0xA0, 0xE0],
+    [0xE0, 0x80, 0xE0, 0x80, 0xE0],
+    [0xE0, 0x80, 0xC0, 0x80, 0x80]
+];
+
+const FISH_N_CHIPS: [[u8; 5]; 16] = [
+    [0x60, 0xA0, 0xA0, 0xA0, 0xC0],
+    [0x40, 0xC0, 0x40, 0x40, 0xE0],
+    [0xC0, 0x20, 0x40, 0x80, 0xE0],
+    [0xC0, 0x20, 0x40, 0x20, 0xC0],
+    [0x20, 0xA0, 0xE0, 0x20, 0x20],
+    [0xE0, 0x80, 0xC0, 0x20, 0xC0],
+    [0x40, 0x80, 0xC0, 0xA0, 0x40],
+    [0xE0, 0x20, 0x60, 0x40, 0x40],
+    [0x40, 0xA0, 0x40, 0xA0, 0x40],
+    [0x40, 0xA0, 0x60, 0x20, 0x40],
+    [0x40, 0xA0, 0xE0, 0xA0, 0xA0],
+    [0xC0, 0xA0, 0xC0, 0xA0, 0xC0],
+    [0x60, 0x80, 0x80, 0x80, 0x60],
+    [0xC0, 0xA0, 0xA0, 0xA0, 0xC0],
+    [0xE0, 0x80, 0xC0, 0x80, 0xE0],
+    [0xE0, 0x80, 0xC0, 0x80, 0x80]
+];
+
+/// SCHIP 1.1 only has large glyphs for decimal digits.
+const SCHIP: [[u8; 10]; 10] = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C],
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C],
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF],
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C],
+    [0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C],
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C]
+];
+
+const OCTO_LARGE: [[u8; 10]; 16] = [
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+    [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+    [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+    [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0]
+];
+
+/// Small 4x5 fonts, one glyph of 5 bytes per hexadecimal digit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Font {
+    /// The font most modern interpreters use, see [`SPRITES`].
+    #[default]
+    Octo,
+    /// The font of the COSMAC VIP interpreter ROM.
+    Vip,
+    Dream6800,
+    Eti660,
+    /// The font of the Fish 'N' Chips interpreter.
+    FishNChips,
+}
+
+impl Font {
+    pub const ALL: [Font; 5] = [Font::Octo, Font::Vip, Font::Dream6800, Font::Eti660, Font::FishNChips];
+
+    /// Bytes of each glyph.
+    pub const GLYPH_SIZE: usize = 5;
+
+    pub fn glyphs(self) -> &'static [[u8; 5]; 16] {
+        match self {
+            Font::Octo => &SPRITES,
+            Font::Vip => &VIP,
+            Font::Dream6800 => &DREAM_6800,
+            Font::Eti660 => &ETI_660,
+            Font::FishNChips => &FISH_N_CHIPS,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Font::Octo => "octo",
+            Font::Vip => "vip",
+            Font::Dream6800 => "dream6800",
+            Font::Eti660 => "eti660",
+            Font::FishNChips => "fish",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Font::ALL.iter().copied().find(|font| font.name() == name)
+    }
+
+    /// Bytes occupied in memory.
+    pub fn size(self) -> usize {
+        self.glyphs().len() * Font::GLYPH_SIZE
+    }
+}
+
+/// Large 8x10 fonts of SCHIP and its successors, one glyph of 10 bytes per digit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LargeFont {
+    /// SCHIP 1.1 font, for the decimal digits only.
+    #[default]
+    Schip,
+    /// Font of Octo, covering every hexadecimal digit.
+    Octo,
+}
+
+impl LargeFont {
+    pub const ALL: [LargeFont; 2] = [LargeFont::Schip, LargeFont::Octo];
+
+    /// Bytes of each glyph.
+    pub const GLYPH_SIZE: usize = 10;
+
+    pub fn glyphs(self) -> &'static [[u8; 10]] {
+        match self {
+            LargeFont::Schip => &SCHIP,
+            LargeFont::Octo => &OCTO_LARGE,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LargeFont::Schip => "schip",
+            LargeFont::Octo => "octo",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LargeFont::ALL.iter().copied().find(|font| font.name() == name)
+    }
+
+    /// Bytes occupied in memory.
+    pub fn size(self) -> usize {
+        self.glyphs().len() * LargeFont::GLYPH_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        for font in Font::ALL.iter() {
+            assert_eq!(Font::from_name(font.name()), Some(*font));
+            assert_eq!(font.size(), 80);
+        }
+
+        assert_eq!(LargeFont::from_name("schip"), Some(LargeFont::Schip));
+        assert_eq!(LargeFont::Schip.size(), 100);
+        assert_eq!(LargeFont::Octo.size(), 160);
+        assert_eq!(Font::from_name("chip-48"), None);
+    }
+}
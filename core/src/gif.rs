@@ -0,0 +1,313 @@
+//! Animated GIF recording.
+//!
+//! Frames are captured at every frame boundary (60 Hz), identical consecutive frames being merged
+//! into a longer delay, and are only encoded when the recording is finished.
+
+use crate::render::{ Frame, Renderer };
+
+use std::collections::HashMap;
+
+/// Largest code of the LZW dictionary, codes are at most 12 bits long.
+const MAX_CODE: u16 = 4095;
+
+struct Capture {
+    pixels: Vec<u8>,
+    /// Number of 60 Hz frames during which this capture was displayed.
+    frames: u32,
+}
+
+pub struct GifRecorder {
+    renderer: Renderer,
+    width: usize,
+    height: usize,
+    captures: Vec<Capture>,
+}
+
+impl GifRecorder {
+    /// Create a recorder, the scale factor and palette of `renderer` being used for the output.
+    pub fn new(renderer: Renderer) -> Self {
+        GifRecorder { renderer, width: 0, height: 0, captures: Vec::new() }
+    }
+
+    /// Record the current state of `frame`, this must be called once per displayed frame.
+    ///
+    /// Frames with different dimensions than the first captured one are ignored.
+    pub fn capture<F: Frame + ?Sized>(&mut self, frame: &F) {
+        if self.captures.is_empty() {
+            self.width = frame.width();
+            self.height = frame.height();
+        } else if (frame.width(), frame.height()) != (self.width, self.height) {
+            return;
+        }
+
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixels.push(frame.pixel(x, y) & 0x3);
+            }
+        }
+
+        match self.captures.last_mut() {
+            Some(last) if last.pixels == pixels => last.frames += 1,
+            _ => self.captures.push(Capture { pixels, frames: 1 }),
+        }
+    }
+
+    /// Number of distinct frames recorded so far.
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.captures.clear();
+    }
+
+    /// Encode every captured frame as a looping animated GIF.
+    pub fn encode(&self) -> Vec<u8> {
+        let scale = self.renderer.scale().get();
+        let palette = self.renderer.palette();
+        let (width, height) = (self.width * scale, self.height * scale);
+
+        // Monochrome recordings use a 2 colors table, four colors are only needed by planes.
+        let four_colors = self.captures.iter().any(|capture| capture.pixels.iter().any(|&p| p > 1));
+        let bits = if four_colors { 2 } else { 1 };
+
+        let mut output = b"GIF89a".to_vec();
+        output.extend_from_slice(&(width as u16).to_le_bytes());
+        output.extend_from_slice(&(height as u16).to_le_bytes());
+        output.push(0x80 | ((bits - 1) << 4) | (bits - 1));
+        output.extend_from_slice(&[0, 0]);
+
+        for index in 0..(1 << bits) {
+            let color = palette.color(index);
+            output.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        // Netscape extension making the animation loop forever.
+        output.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        output.extend_from_slice(b"NETSCAPE2.0");
+        output.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+        // Delays are in hundredths of a second, they are computed from the total elapsed time
+        // so the rounding errors don't add up.
+        let mut elapsed = 0;
+
+        for capture in &self.captures {
+            let start = elapsed * 100 / 60;
+            elapsed += capture.frames;
+            let delay = (elapsed * 100 / 60 - start).min(u16::MAX as u32) as u16;
+
+            output.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+            output.extend_from_slice(&delay.to_le_bytes());
+            output.extend_from_slice(&[0x00, 0x00]);
+
+            output.push(0x2C);
+            output.extend_from_slice(&[0, 0, 0, 0]);
+            output.extend_from_slice(&(width as u16).to_le_bytes());
+            output.extend_from_slice(&(height as u16).to_le_bytes());
+            output.push(0x00);
+
+            let mut indices = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    indices.push(capture.pixels[(y / scale) * self.width + x / scale]);
+                }
+            }
+
+            // The minimum code size can't be lower than 2, even for 2 colors images.
+            let min_code_size = 2;
+            output.push(min_code_size);
+
+            for block in lzw(&indices, min_code_size).chunks(255) {
+                output.push(block.len() as u8);
+                output.extend_from_slice(block);
+            }
+            output.push(0x00);
+        }
+
+        output.push(0x3B);
+        output
+    }
+}
+
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    length: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.length;
+        self.length += size;
+
+        while self.length >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.length -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.length > 0 {
+            self.output.push(self.buffer as u8);
+        }
+
+        self.output
+    }
+}
+
+/// Variable-length LZW compression, as specified by the GIF format.
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut writer = BitWriter { output: Vec::new(), buffer: 0, length: 0 };
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size + 1;
+
+    writer.write(clear, size);
+
+    let mut pixels = indices.iter();
+    let mut prefix = match pixels.next() {
+        Some(&first) => first as u16,
+        None => {
+            writer.write(end, size);
+            return writer.finish();
+        }
+    };
+
+    for &pixel in pixels {
+        if let Some(&code) = dictionary.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, size);
+
+        if next >= (1 << size) && size < 12 {
+            size += 1;
+        }
+
+        if next >= MAX_CODE {
+            writer.write(clear, size);
+            dictionary.clear();
+            next = end + 1;
+            size = min_code_size + 1;
+        } else {
+            dictionary.insert((prefix, pixel), next);
+            next += 1;
+        }
+
+        prefix = pixel as u16;
+    }
+
+    writer.write(prefix, size);
+    if next >= (1 << size) && size < 12 {
+        size += 1;
+    }
+    writer.write(end, size);
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Palette;
+    use std::num::NonZeroUsize;
+
+    /// Reference LZW decoder, following the GIF specification.
+    fn decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_code_size + 1;
+        let (mut position, mut output, mut previous) = (0, Vec::new(), None::<Vec<u8>>);
+
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear).map(|i| vec![i as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+
+        loop {
+            let mut code = 0;
+            for bit in 0..size as usize {
+                let bit_position = position + bit;
+                code |= (((data[bit_position / 8] >> (bit_position % 8)) & 1) as usize) << bit;
+            }
+            position += size as usize;
+
+            if code == clear {
+                reset(&mut table);
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return output;
+            }
+
+            let entry = match (&previous, table.get(code)) {
+                (_, Some(entry)) => entry.clone(),
+                (Some(previous), None) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                },
+                (None, None) => panic!("invalid code"),
+            };
+
+            if let Some(mut previous) = previous {
+                previous.push(entry[0]);
+                table.push(previous);
+            }
+
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+
+            if table.len() == (1 << size) && size < 12 {
+                size += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn lzw_roundtrip() {
+        let mut state = 0x1234_5678u32;
+        let data: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 4) as u8
+            })
+            .collect();
+
+        assert_eq!(decode(&lzw(&data, 2), 2), data);
+        assert_eq!(decode(&lzw(&[1; 5000], 2), 2), vec![1; 5000]);
+    }
+
+    #[test]
+    fn identical_frames_are_merged() {
+        let renderer = Renderer::new(NonZeroUsize::new(2).unwrap(), Palette::DEFAULT);
+        let mut recorder = GifRecorder::new(renderer);
+
+        recorder.capture(&[[true, false]]);
+        recorder.capture(&[[true, false]]);
+        recorder.capture(&[[false, true]]);
+
+        assert_eq!(recorder.len(), 2);
+
+        let gif = recorder.encode();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[4, 0, 2, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+}
@@ -0,0 +1,91 @@
+//! Services of the host running a program.
+//!
+//! Without `std`, the crate can't reach the operating system: random numbers, sound and the
+//! screen belong to the board running it. [`Hooks`] are plain function pointers the program
+//! calls from its execution path, so they work without a heap. Every hook is optional.
+
+use crate::display::Display;
+
+/// Functions called by a [`Program`](crate::program::Program), see
+/// [`set_hooks`](crate::program::Program::set_hooks).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hooks {
+    /// Source of the random bytes of `CXNN`, instead of the built-in generator.
+    pub random: Option<fn() -> u8>,
+    /// Called with the delay and sound timers once they are decremented, at 60 Hz: the sound
+    /// timer being non-zero means the buzzer is on.
+    pub timers: Option<fn(u8, u8)>,
+    /// Called with the display at the end of every
+    /// [`run_frame`](crate::program::Program::run_frame).
+    pub frame: Option<fn(&Display)>,
+}
+
+/// Seed of the built-in generator when the system can't provide one.
+const DEFAULT_SEED: u32 = 0x2545_F491;
+
+/// The xorshift32 generator used when there is no [`Hooks::random`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct XorShift(u32);
+
+impl XorShift {
+    /// Generator seeded with `seed`, which can't be zero: it is replaced by a fixed seed.
+    pub(crate) fn new(seed: u32) -> Self {
+        XorShift(if seed == 0 { DEFAULT_SEED } else { seed })
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        let mut state = self.0;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.0 = state;
+
+        (state >> 24) as u8
+    }
+}
+
+impl Default for XorShift {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        XorShift::new(rand::random())
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        XorShift::new(DEFAULT_SEED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{ Program, Register };
+
+    #[test]
+    fn seeded_generator() {
+        let mut first = XorShift::new(1);
+        let mut second = XorShift::new(1);
+        let bytes: [u8; 8] = [0; 8].map(|_| first.next_byte());
+
+        assert_eq!(bytes, [0; 8].map(|_| second.next_byte()));
+        assert!(bytes.iter().any(|&byte| byte != bytes[0]));
+        assert_eq!(XorShift::new(0).next_byte(), XorShift::new(DEFAULT_SEED).next_byte());
+    }
+
+    #[test]
+    fn program_hooks() {
+        // v0 = random & 0xFF, delay timer = v0.
+        let mut program = Program::new();
+        program.load(&[0xC0, 0xFF, 0xF0, 0x15]).unwrap();
+        program.set_hooks(Hooks {
+            random: Some(|| 0x42),
+            timers: Some(|delay, sound| assert_eq!((delay, sound), (0x41, 0))),
+            frame: Some(|display| assert_eq!(display.width(), 64)),
+        });
+        program.set_instructions_per_frame(2);
+
+        program.run_frame();
+        assert_eq!(program.register(Register::V0), 0x42);
+        assert_eq!(program.delay_timer, 0x41);
+    }
+}
@@ -1,6 +1,11 @@
+use crate::bus::Bus;
+use crate::chip8x;
+use crate::font::{ Font, LargeFont };
+use crate::keypad::Key;
+use crate::platform::Platform;
 use crate::program::{ Cursor, Program };
-
-use rand::Rng;
+use crate::state::{ ExecutionState, Fault, HaltReason };
+use crate::sys::{ self, SysPolicy };
 
 fn address(x: u8, y: u8, n: u8) -> u16 {
     (((x as u16) << 8) & 0xF00) | value(y, n) as u16
@@ -10,6 +15,43 @@ fn value(y: u8, n: u8) -> u8 {
     ((y << 4) & 0xF0)| (n & 0xF)
 }
 
+/// Fault if the `length` bytes starting at `start` can't all be read, or written if `write`.
+fn inaccessible(program: &Program, start: usize, length: usize, write: bool) -> Option<Cursor> {
+    program.check_access(start, length, write).err().map(Cursor::Fault)
+}
+
+/// Run `access` to the memory, turning its fault into the cursor of the instruction.
+macro_rules! bus {
+    ($access:expr) => {
+        match $access {
+            Ok(value) => value,
+            Err(fault) => return Cursor::Fault(fault),
+        }
+    };
+}
+
+/// Reset VF after a logical operation, with the `logic` quirk.
+fn reset_flag(program: &mut Program) {
+    if program.quirks().logic {
+        program.v[0xF] = 0;
+    }
+}
+
+/// Register shifted by `8XY6` and `8XYE`, depending on the `shift` quirk.
+fn shifted(program: &Program, x: usize, y: usize) -> usize {
+    if program.quirks().shift { x } else { y }
+}
+
+/// Move I past the registers stored or read by `FX55` and `FX65`, depending on the quirks.
+fn increment_i(program: &mut Program, x: usize) {
+    let quirks = program.quirks();
+
+    if !quirks.memory_leave_i_unchanged {
+        let increment = if quirks.memory_increment_by_x { x } else { x + 1 };
+        program.i = program.i.wrapping_add(increment as u16);
+    }
+}
+
 macro_rules! instructions {
     (
         $(
@@ -23,8 +65,9 @@ macro_rules! instructions {
     ) => {
         $(
             $(#[$meta])*
+            #[derive(Clone, Copy, Debug, PartialEq, Eq)]
             pub struct $instruction {
-                $($($field: $type),*)*
+                $($(pub(crate) $field: $type),*)*
             }
 
             impl $instruction {
@@ -38,6 +81,7 @@ macro_rules! instructions {
         ///
         /// Description of each instruction retrieved from the
         /// [Cowngod's Chip-8 Technical Reference v1.0](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM)
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub enum Instruction {
             $(
                 $(#[$meta])*
@@ -82,7 +126,7 @@ instructions! {
     /// Clear the display.
     (0x0, 0x0, 0xE, 0x0) => Clear,
     fn run(&self, program: &mut Program) -> Cursor {
-        program.screen = [[false; 64]; 32];
+        program.screen.clear();
 
         Cursor::Next
     },
@@ -93,18 +137,75 @@ instructions! {
     /// subtracts 1 from the stack pointer.
     (0x0, 0x0, 0xE, 0xE) => ReturnSubroutine,
     fn run(&self, program: &mut Program) -> Cursor {
+        if program.stack_pointer == 0 {
+            return Cursor::Fault(Fault::StackUnderflow { address: program.program_counter });
+        }
+
         program.stack_pointer -= 1;
 
         Cursor::Jump(program.stack[program.stack_pointer as usize])
     },
 
+    /// Exit the interpreter (SCHIP).
+    (0x0, 0x0, 0xF, 0xD) => Exit,
+    fn run(&self, _program: &mut Program) -> Cursor {
+        Cursor::Halt(HaltReason::Exit)
+    },
+
+    /// Call the machine code routine at `address`.
+    ///
+    /// The routine is handled by the [`SysHandler`](crate::sys::SysHandler) of the program if
+    /// it knows it, then by the routines built in the platform of the program, otherwise the
+    /// [`SysPolicy`] of the program applies.
+    (0x0, x, y, n) => Sys {
+        address: u16 = address(x, y, n)
+    },
+    fn run(&self, program: &mut Program) -> Cursor {
+        #[cfg(feature = "alloc")]
+        if let Some(mut handler) = program.sys_handler.take() {
+            let cursor = handler.call(self.address, program);
+            program.sys_handler = Some(handler);
+
+            if let Some(cursor) = cursor {
+                return cursor;
+            }
+        }
+
+        if let Some(cursor) = sys::builtin(self.address, program) {
+            return cursor;
+        }
+
+        match program.sys_policy {
+            SysPolicy::Ignore => Cursor::Next,
+            SysPolicy::Fault => Cursor::Fault(Fault::UnhandledSysCall {
+                address: program.program_counter,
+                routine: self.address
+            })
+        }
+    },
+
     /// Jump to location `address`.
     ///
     /// The interpreter sets the program counter to `address`.
     (0x1, x, y, n) => JumpTo {
         address: u16 = address(x, y, n)
     },
-    fn run(&self, _program: &mut Program) -> Cursor {
+    fn run(&self, program: &mut Program) -> Cursor {
+        // Nothing but a key wait could get the program out of a jump to itself, it is how most
+        // games end.
+        if self.address == program.program_counter {
+            return Cursor::Halt(HaltReason::InfiniteLoop { address: self.address });
+        }
+
+        // Hires ROMs start by jumping over the patch of the interpreter, which sets up the 64x64
+        // display and then runs the program from 0x2C0.
+        if program.platform() == Some(Platform::HiresChip8)
+            && program.program_counter == 0x200
+            && self.address == 0x260
+        {
+            return Cursor::Jump(0x2C0);
+        }
+
         Cursor::Jump(self.address)
     },
 
@@ -116,7 +217,11 @@ instructions! {
         address: u16 = address(x, y, n)
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.stack[program.stack_pointer as usize] = program.program_counter + 2;
+        if program.stack_pointer as usize >= program.config.stack_depth {
+            return Cursor::Fault(Fault::StackOverflow { address: program.program_counter });
+        }
+
+        program.stack[program.stack_pointer as usize] = program.program_counter.wrapping_add(2);
         program.stack_pointer += 1;
 
         Cursor::Jump(self.address)
@@ -170,6 +275,24 @@ instructions! {
         }
     },
 
+    /// Set Vx = Vx + Vy, nibble by nibble (CHIP-8X).
+    ///
+    /// Only the 3 lowest bits of each nibble are added, carries between nibbles being dropped.
+    (0x5, x, y, 0x1) => SetVxToVxAddVyPacked {
+        x: usize = x as usize,
+        y: usize = y as usize
+    },
+    fn run(&self, program: &mut Program) -> Cursor {
+        let opcode = 0x5001 | (self.x as u16) << 8 | (self.y as u16) << 4;
+        if let Some(fault) = chip8x::only(program, opcode) {
+            return fault;
+        }
+
+        program.v[self.x] = ((program.v[self.x] & 0x77) + (program.v[self.y] & 0x77)) & 0x77;
+
+        Cursor::Next
+    },
+
     /// Set Vx = kk.
     ///
     /// The interpreter puts the value kk into register Vx.
@@ -221,6 +344,7 @@ instructions! {
     },
     fn run(&self, program: &mut Program) -> Cursor {
         program.v[self.x] |= program.v[self.y];
+        reset_flag(program);
 
         Cursor::Next
     },
@@ -236,6 +360,7 @@ instructions! {
     },
     fn run(&self, program: &mut Program) -> Cursor {
         program.v[self.x] &= program.v[self.y];
+        reset_flag(program);
 
         Cursor::Next
     },
@@ -251,6 +376,7 @@ instructions! {
     },
     fn run(&self, program: &mut Program) -> Cursor {
         program.v[self.x] ^= program.v[self.y];
+        reset_flag(program);
 
         Cursor::Next
     },
@@ -291,13 +417,16 @@ instructions! {
     /// Set Vx = Vx SHR 1.
     ///
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is
-    /// divided by 2.
-    (0x8, x, _, 0x6) => SetVxToVxShr {
-        x: usize = x as usize
+    /// divided by 2. Without the `shift` quirk, Vy is shifted instead of Vx.
+    (0x8, x, y, 0x6) => SetVxToVxShr {
+        x: usize = x as usize,
+        y: usize = y as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.v[0xF] = program.v[self.x] & 1;
-        program.v[self.x] >>= 1;
+        let value = program.v[shifted(program, self.x, self.y)];
+
+        program.v[self.x] = value >> 1;
+        program.v[0xF] = value & 1;
 
         Cursor::Next
     },
@@ -320,13 +449,16 @@ instructions! {
     /// Set Vx = Vx SHL 1.
     ///
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
-    /// Then Vx is multiplied by 2.
-    (0x8, x, _, 0xE) => SetVxToVxShl {
-        x: usize = x as usize
+    /// Then Vx is multiplied by 2. Without the `shift` quirk, Vy is shifted instead of Vx.
+    (0x8, x, y, 0xE) => SetVxToVxShl {
+        x: usize = x as usize,
+        y: usize = y as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.v[0xF] = (program.v[self.x] >> 7) & 1;
-        program.v[self.x] <<= 1;
+        let value = program.v[shifted(program, self.x, self.y)];
+
+        program.v[self.x] = value << 1;
+        program.v[0xF] = value >> 7;
 
         Cursor::Next
     },
@@ -361,12 +493,23 @@ instructions! {
 
     /// Jump to location `address` + V0.
     ///
-    /// The program counter is set to `address` plus the value of V0.
+    /// The program counter is set to `address` plus the value of V0. With the `jump` quirk, the
+    /// register is Vx, x being the highest nibble of `address`.
+    ///
+    /// On CHIP-8X, `BXYN` sets the colours of the screen instead, see
+    /// [`chip8x`](crate::chip8x).
     (0xB, x, y, n) => JumpToPlusV0 {
         address: u16 = address(x, y, n)
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        Cursor::Jump(program.v[0] as u16 + self.address)
+        if program.platform() == Some(Platform::Chip8X) {
+            let nibble = |shift: u16| (self.address >> shift) as usize & 0xF;
+            return chip8x::set_colors(program, nibble(8), nibble(4), nibble(0));
+        }
+
+        let register = if program.quirks().jump { (self.address >> 8) as usize } else { 0 };
+
+        Cursor::Jump(program.v[register] as u16 + self.address)
     },
 
     /// Set Vx = random byte AND kk.
@@ -378,7 +521,7 @@ instructions! {
         value: u8 = value(y, n)
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.v[self.x] = program.rng.gen::<u8>() & self.value;
+        program.v[self.x] = program.random_byte() & self.value;
 
         Cursor::Next
     },
@@ -389,25 +532,50 @@ instructions! {
     /// are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the
     /// existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is
     /// set to 0. If the sprite is positioned so part of it is outside the coordinates of the
-    /// display, it wraps around to the opposite side of the screen.
+    /// display, it wraps around to the opposite side of the screen with the `wrap` quirk, and is
+    /// clipped otherwise. With the `vblank` quirk, only one sprite is drawn per frame.
     (0xD, x, y, n) => Draw {
         x: usize = x as usize,
         y: usize = y as usize,
         n: usize = n as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
+        if let Some(fault) = inaccessible(program, program.i as usize, self.n, false) {
+            return fault;
+        }
+
+        let quirks = program.quirks();
+
+        if quirks.vblank && program.frame_drawn {
+            return Cursor::Stay;
+        }
+
+        program.frame_drawn = true;
         program.v[0xF] = 0;
 
+        let (width, height) = (program.screen.width(), program.screen.height());
+        // The position itself always wraps, only the pixels past the edges are clipped.
+        let left = program.v[self.x] as usize % width;
+        let top = program.v[self.y] as usize % height;
+
         for byte in 0..self.n {
-            let y = (program.v[self.y] as usize + byte) % 32;
-            let byte = program.memory[program.i as usize + byte];
+            if !quirks.wrap && top + byte >= height {
+                break;
+            }
+
+            let y = (top + byte) % height;
+            let byte = bus!(program.read(program.i as usize + byte));
 
             for bit in 0..8 {
-                let x = (program.v[self.x] as usize + bit) % 64;
-                let bit = ((byte >> (7 - bit)) & 1) == 1;
+                if !quirks.wrap && left + bit >= width {
+                    break;
+                }
+
+                let x = (left + bit) % width;
 
-                program.v[0xF] |= if bit & program.screen[y][x] { 1 } else { 0 };
-                program.screen[y][x] ^= bit;
+                if (byte >> (7 - bit)) & 1 == 1 && program.screen.toggle(x, y) {
+                    program.v[0xF] = 1;
+                }
             }
         }
 
@@ -422,7 +590,7 @@ instructions! {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        if program.keypad[program.v[self.x] as usize] {
+        if program.is_key_pressed(Key::from_nibble(program.v[self.x])) {
             Cursor::Skip
         } else {
             Cursor::Next
@@ -437,7 +605,41 @@ instructions! {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        if !program.keypad[program.v[self.x] as usize] {
+        if !program.is_key_pressed(Key::from_nibble(program.v[self.x])) {
+            Cursor::Skip
+        } else {
+            Cursor::Next
+        }
+    },
+
+    /// Skip next instruction if key with the value of Vx is pressed on the second keypad
+    /// (CHIP-8X).
+    (0xE, x, 0xF, 0x2) => SkipSecondKeyPressed {
+        x: usize = x as usize
+    },
+    fn run(&self, program: &mut Program) -> Cursor {
+        if let Some(fault) = chip8x::only(program, 0xE0F2 | (self.x as u16) << 8) {
+            return fault;
+        }
+
+        if program.second_keypad.is_pressed(Key::from_nibble(program.v[self.x])) {
+            Cursor::Skip
+        } else {
+            Cursor::Next
+        }
+    },
+
+    /// Skip next instruction if key with the value of Vx is not pressed on the second keypad
+    /// (CHIP-8X).
+    (0xE, x, 0xF, 0x5) => SkipSecondKeyNotPressed {
+        x: usize = x as usize
+    },
+    fn run(&self, program: &mut Program) -> Cursor {
+        if let Some(fault) = chip8x::only(program, 0xE0F5 | (self.x as u16) << 8) {
+            return fault;
+        }
+
+        if !program.second_keypad.is_pressed(Key::from_nibble(program.v[self.x])) {
             Cursor::Skip
         } else {
             Cursor::Next
@@ -458,14 +660,19 @@ instructions! {
     /// Wait for a key press, store the value of the key in Vx.
     ///
     /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
-    (0xF, x, 0x0, 0x4) => SetVxToNextKeyPress {
+    /// As on the COSMAC VIP, the key must also be released before execution resumes.
+    (0xF, x, 0x0, 0xA) => SetVxToNextKeyPress {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        if let Some((i, _)) = program.keypad.iter().enumerate().find(|&(_, &value)| value) {
-            program.v[self.x] = i as u8;
+        program.keypad.start_wait();
+
+        if let Some(key) = program.keypad.poll_wait() {
+            program.v[self.x] = key.value();
+            program.state = ExecutionState::Running;
             Cursor::Next
         } else {
+            program.state = ExecutionState::WaitingForKey { register: self.x as u8 };
             Cursor::Stay
         }
     },
@@ -493,7 +700,7 @@ instructions! {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.i += program.v[self.x] as u16;
+        program.i = program.i.wrapping_add(program.v[self.x] as u16);
         Cursor::Next
     },
 
@@ -505,7 +712,31 @@ instructions! {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        program.i = program.v[self.x] as u16 * 5;
+        let digit = (program.v[self.x] & 0xF) as usize;
+
+        program.i = (program.config.font_address as usize + digit * Font::GLYPH_SIZE) as u16;
+        Cursor::Next
+    },
+
+    /// Set I = location of the large sprite for digit Vx (SCHIP).
+    ///
+    /// Only available when the program has a large font. SCHIP 1.1 only has glyphs for decimal
+    /// digits, other values point past the end of the font.
+    (0xF, x, 0x3, 0x0) => SetIToLargeSpriteLocation {
+        x: usize = x as usize
+    },
+    fn run(&self, program: &mut Program) -> Cursor {
+        if program.config.large_font.is_none() {
+            let address = program.program_counter;
+            let opcode = 0xF030 | (self.x as u16) << 8;
+
+            return Cursor::Fault(Fault::InvalidInstruction { address, opcode });
+        }
+
+        let digit = (program.v[self.x] & 0xF) as usize;
+        let address = program.config.large_font_address as usize + digit * LargeFont::GLYPH_SIZE;
+
+        program.i = address as u16;
         Cursor::Next
     },
 
@@ -520,41 +751,55 @@ instructions! {
         let idx = program.i as usize;
         let value = program.v[self.x];
 
-        program.memory[idx] = value / 100;
-        program.memory[idx + 1] = (value % 100) / 10;
-        program.memory[idx + 2] = value % 10;
+        if let Some(fault) = inaccessible(program, idx, 3, true) {
+            return fault;
+        }
+
+        bus!(program.write(idx, value / 100));
+        bus!(program.write(idx + 1, (value % 100) / 10));
+        bus!(program.write(idx + 2, value % 10));
         Cursor::Next
     },
 
     /// Store registers V0 through Vx in memory starting at location I.
     ///
     /// The interpreter copies the values of registers V0 through Vx into memory,
-    /// starting at the address in I.
+    /// starting at the address in I. I is then moved past them, unless the
+    /// `memory_leave_i_unchanged` quirk is enabled.
     (0xF, x, 0x5, 0x5) => StoreRegisters {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        // TODO: Use copy from slice
+        if let Some(fault) = inaccessible(program, program.i as usize, self.x + 1, true) {
+            return fault;
+        }
+
         for i in 0..=self.x {
-            program.memory[program.i as usize + i] = program.v[i];
+            bus!(program.write(program.i as usize + i, program.v[i]));
         }
 
+        increment_i(program, self.x);
         Cursor::Next
     },
 
     /// Read registers V0 through Vx from memory starting at location I.
     ///
     /// The interpreter reads values from memory starting at location I into registers V0 through Vx.
+    /// I is then moved past them, unless the `memory_leave_i_unchanged` quirk is enabled.
     /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
     (0xF, x, 0x6, 0x5) => ReadRegisters {
         x: usize = x as usize
     },
     fn run(&self, program: &mut Program) -> Cursor {
-        // TODO: Use copy from slice
+        if let Some(fault) = inaccessible(program, program.i as usize, self.x + 1, false) {
+            return fault;
+        }
+
         for i in 0..=self.x {
-            program.v[i] = program.memory[program.i as usize + i];
+            program.v[i] = bus!(program.read(program.i as usize + i));
         }
 
+        increment_i(program, self.x);
         Cursor::Next
     },
 
@@ -564,8 +809,9 @@ instructions! {
         c: u8 = c,
         d: u8 = d
     },
-    fn run(&self, _program: &mut Program) -> Cursor {
-        panic!("invalid instruction ({:x}, {:x}, {:x}, {:x})", self.a, self.b, self.c, self.d);
-        unreachable!()
+    fn run(&self, program: &mut Program) -> Cursor {
+        let opcode = u16::from_be_bytes([(self.a << 4) | self.b, (self.c << 4) | self.d]);
+
+        Cursor::Fault(Fault::InvalidInstruction { address: program.program_counter, opcode })
     }
 }
@@ -0,0 +1,422 @@
+//! The hexadecimal keypad, and the mappings from keyboards and gamepads to it.
+//!
+//! The keypad of the COSMAC VIP is laid out as follows, and is usually mapped to the 4x4 block
+//! at the left of a keyboard (`1234`, `QWER`, `ASDF` and `ZXCV` on a QWERTY keyboard):
+//!
+//! ```text
+//! 1 2 3 C
+//! 4 5 6 D
+//! 7 8 9 E
+//! A 0 B F
+//! ```
+
+use core::convert::TryFrom;
+use core::error::Error;
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Key {
+    Zero = 0x0,
+    One = 0x1,
+    Two = 0x2,
+    Three = 0x3,
+    Four = 0x4,
+    Five = 0x5,
+    Six = 0x6,
+    Seven = 0x7,
+    Eight = 0x8,
+    Nine = 0x9,
+    A = 0xA,
+    B = 0xB,
+    C = 0xC,
+    D = 0xD,
+    E = 0xE,
+    F = 0xF,
+}
+
+impl Key {
+    /// Every key, ordered by value.
+    pub const ALL: [Key; 16] = [
+        Key::Zero, Key::One, Key::Two, Key::Three,
+        Key::Four, Key::Five, Key::Six, Key::Seven,
+        Key::Eight, Key::Nine, Key::A, Key::B,
+        Key::C, Key::D, Key::E, Key::F,
+    ];
+
+    /// Every key, ordered as on the physical keypad, row by row.
+    pub const LAYOUT: [[Key; 4]; 4] = [
+        [Key::One, Key::Two, Key::Three, Key::C],
+        [Key::Four, Key::Five, Key::Six, Key::D],
+        [Key::Seven, Key::Eight, Key::Nine, Key::E],
+        [Key::A, Key::Zero, Key::B, Key::F],
+    ];
+
+    pub fn value(self) -> u8 {
+        self as u8
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Key corresponding to the lowest nibble of `value`, which is what the interpreter looks
+    /// at when comparing a register with the keypad.
+    pub fn from_nibble(value: u8) -> Self {
+        Key::ALL[(value & 0xF) as usize]
+    }
+}
+
+impl From<Key> for u8 {
+    fn from(key: Key) -> Self {
+        key.value()
+    }
+}
+
+impl TryFrom<u8> for Key {
+    type Error = InvalidKey;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Key::ALL.get(value as usize).copied().ok_or(InvalidKey(value as usize))
+    }
+}
+
+impl TryFrom<usize> for Key {
+    type Error = InvalidKey;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        Key::ALL.get(value).copied().ok_or(InvalidKey(value))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}", self.value())
+    }
+}
+
+/// Error returned when converting a value outside of `0x0..=0xF` into a [`Key`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidKey(pub usize);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid key {:#x}, keys range from 0x0 to 0xF", self.0)
+    }
+}
+
+impl Error for InvalidKey {}
+
+/// State of the keypad, fed with press and release events by the frontend.
+///
+/// Besides which keys are currently down, the keypad records the transitions happening while the
+/// interpreter waits for a key (`FX0A`). Like the original COSMAC VIP interpreter, the wait only
+/// ends once a key has been pressed *and released*, so a key held down across several `FX0A`
+/// doesn't make a game skip screens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keypad {
+    pressed: u16,
+    wait: Option<Wait>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Wait {
+    /// Keys pressed since the beginning of the wait.
+    pressed: u16,
+    released: Option<Key>,
+}
+
+impl Keypad {
+    pub fn press(&mut self, key: Key) {
+        self.pressed |= 1 << key.index();
+
+        if let Some(wait) = self.wait.as_mut() {
+            wait.pressed |= 1 << key.index();
+        }
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.pressed &= !(1 << key.index());
+
+        if let Some(wait) = self.wait.as_mut() {
+            if wait.released.is_none() && wait.pressed & (1 << key.index()) != 0 {
+                wait.released = Some(key);
+            }
+        }
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed & (1 << key.index()) != 0
+    }
+
+    /// Keys currently down.
+    pub fn pressed(&self) -> impl Iterator<Item = Key> + '_ {
+        Key::ALL.iter().copied().filter(move |&key| self.is_pressed(key))
+    }
+
+    /// Release every key, e.g. when the frontend loses focus.
+    pub fn release_all(&mut self) {
+        for key in Key::ALL.iter() {
+            self.release(*key);
+        }
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.wait.is_some()
+    }
+
+    /// Wait for a key to be pressed and released. As on the VIP, a key already down counts as
+    /// pressed.
+    pub(crate) fn start_wait(&mut self) {
+        if self.wait.is_none() {
+            self.wait = Some(Wait { pressed: self.pressed, released: None });
+        }
+    }
+
+    /// Stop waiting without a key.
+    pub(crate) fn cancel_wait(&mut self) {
+        self.wait = None;
+    }
+
+    /// End the current wait if a key was pressed and released, returning that key.
+    pub(crate) fn poll_wait(&mut self) -> Option<Key> {
+        let key = self.wait.and_then(|wait| wait.released)?;
+        self.wait = None;
+
+        Some(key)
+    }
+}
+
+/// Keyboard layouts, used to map the characters typed by the user to the keypad.
+///
+/// Whatever the layout, the keypad is mapped to the same physical 4x4 block of keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+}
+
+impl KeyboardLayout {
+    /// Characters of the 4x4 block, row by row, lowercase.
+    fn rows(self) -> [[char; 4]; 4] {
+        match self {
+            KeyboardLayout::Qwerty => [
+                ['1', '2', '3', '4'],
+                ['q', 'w', 'e', 'r'],
+                ['a', 's', 'd', 'f'],
+                ['z', 'x', 'c', 'v'],
+            ],
+            KeyboardLayout::Azerty => [
+                ['&', 'é', '"', '\''],
+                ['a', 'z', 'e', 'r'],
+                ['q', 's', 'd', 'f'],
+                ['w', 'x', 'c', 'v'],
+            ],
+            KeyboardLayout::Qwertz => [
+                ['1', '2', '3', '4'],
+                ['q', 'w', 'e', 'r'],
+                ['a', 's', 'd', 'f'],
+                ['y', 'x', 'c', 'v'],
+            ],
+            KeyboardLayout::Dvorak => [
+                ['1', '2', '3', '4'],
+                ['\'', ',', '.', 'p'],
+                ['a', 'o', 'e', 'u'],
+                [';', 'q', 'j', 'k'],
+            ],
+        }
+    }
+
+    /// Key of the keypad mapped to the typed `character`, letters being case insensitive.
+    ///
+    /// On AZERTY keyboards, digits of the first row are accepted as well as the characters
+    /// typed without shift.
+    pub fn key(self, character: char) -> Option<Key> {
+        let character = character.to_lowercase().next().unwrap_or(character);
+        let digit_row = ['1', '2', '3', '4'];
+
+        self.rows()
+            .iter()
+            .enumerate()
+            .flat_map(|(row, characters)| {
+                characters.iter().enumerate().map(move |(column, c)| (row, column, *c))
+            })
+            .find(|&(row, column, c)| {
+                c == character || (row == 0 && digit_row[column] == character)
+            })
+            .map(|(row, column, _)| Key::LAYOUT[row][column])
+    }
+
+    /// Same as [`key`](Self::key) for the `key` value of a browser `KeyboardEvent`, only single
+    /// character values can match.
+    pub fn key_from_str(self, value: &str) -> Option<Key> {
+        let mut characters = value.chars();
+
+        match (characters.next(), characters.next()) {
+            (Some(character), None) => self.key(character),
+            _ => None,
+        }
+    }
+}
+
+/// Key of the keypad mapped to a physical key, identified by its layout-independent `code`, as
+/// found in browser `KeyboardEvent`s (e.g. `KeyQ` for the key labelled Q on a QWERTY keyboard).
+pub fn key_from_code(code: &str) -> Option<Key> {
+    const CODES: [[&str; 4]; 4] = [
+        ["Digit1", "Digit2", "Digit3", "Digit4"],
+        ["KeyQ", "KeyW", "KeyE", "KeyR"],
+        ["KeyA", "KeyS", "KeyD", "KeyF"],
+        ["KeyZ", "KeyX", "KeyC", "KeyV"],
+    ];
+
+    CODES.iter().enumerate().find_map(|(row, codes)| {
+        codes.iter().position(|&c| c == code).map(|column| Key::LAYOUT[row][column])
+    })
+}
+
+/// Buttons of a gamepad, numbered as in the standard mapping of the web Gamepad API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South = 0,
+    East = 1,
+    West = 2,
+    North = 3,
+    LeftBumper = 4,
+    RightBumper = 5,
+    LeftTrigger = 6,
+    RightTrigger = 7,
+    Select = 8,
+    Start = 9,
+    LeftStick = 10,
+    RightStick = 11,
+    Up = 12,
+    Down = 13,
+    Left = 14,
+    Right = 15,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 16] = [
+        GamepadButton::South, GamepadButton::East, GamepadButton::West, GamepadButton::North,
+        GamepadButton::LeftBumper, GamepadButton::RightBumper,
+        GamepadButton::LeftTrigger, GamepadButton::RightTrigger,
+        GamepadButton::Select, GamepadButton::Start,
+        GamepadButton::LeftStick, GamepadButton::RightStick,
+        GamepadButton::Up, GamepadButton::Down, GamepadButton::Left, GamepadButton::Right,
+    ];
+
+    /// Button of the standard mapping with the given `index`.
+    pub fn from_index(index: usize) -> Option<Self> {
+        GamepadButton::ALL.get(index).copied()
+    }
+}
+
+/// Keys of the keypad bound to each gamepad button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GamepadMapping {
+    buttons: [Option<Key>; 16],
+}
+
+impl GamepadMapping {
+    /// A mapping with no button bound.
+    pub fn empty() -> Self {
+        GamepadMapping { buttons: [None; 16] }
+    }
+
+    pub fn bind(&mut self, button: GamepadButton, key: Key) -> &mut Self {
+        self.buttons[button as usize] = Some(key);
+        self
+    }
+
+    pub fn unbind(&mut self, button: GamepadButton) -> &mut Self {
+        self.buttons[button as usize] = None;
+        self
+    }
+
+    pub fn key(&self, button: GamepadButton) -> Option<Key> {
+        self.buttons[button as usize]
+    }
+}
+
+impl Default for GamepadMapping {
+    /// The directional pad is bound to `5`, `7`, `8` and `9` (`W`, `A`, `S` and `D` on a QWERTY
+    /// keyboard), which most games use to move, and the face buttons to the keys around them.
+    fn default() -> Self {
+        let mut mapping = GamepadMapping::empty();
+
+        mapping
+            .bind(GamepadButton::Up, Key::Five)
+            .bind(GamepadButton::Left, Key::Seven)
+            .bind(GamepadButton::Down, Key::Eight)
+            .bind(GamepadButton::Right, Key::Nine)
+            .bind(GamepadButton::South, Key::Six)
+            .bind(GamepadButton::East, Key::Four)
+            .bind(GamepadButton::West, Key::A)
+            .bind(GamepadButton::North, Key::B)
+            .bind(GamepadButton::Start, Key::F)
+            .bind(GamepadButton::Select, Key::Zero);
+
+        mapping
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Key::try_from(0xAu8), Ok(Key::A));
+        assert_eq!(Key::try_from(0x10usize), Err(InvalidKey(0x10)));
+        assert_eq!(Key::from_nibble(0x1F), Key::F);
+        assert!(Key::ALL.iter().enumerate().all(|(value, key)| key.index() == value));
+    }
+
+    #[test]
+    fn layouts_map_the_same_block() {
+        let block = [
+            (KeyboardLayout::Qwerty, ['1', 'q', 'a', 'z', 'v']),
+            (KeyboardLayout::Azerty, ['&', 'a', 'q', 'w', 'v']),
+            (KeyboardLayout::Qwertz, ['1', 'q', 'a', 'y', 'v']),
+            (KeyboardLayout::Dvorak, ['1', '\'', 'a', ';', 'k']),
+        ];
+
+        for (layout, characters) in &block {
+            let keys: Vec<_> = characters.iter().map(|&c| layout.key(c)).collect();
+            let expected = [Key::One, Key::Four, Key::Seven, Key::A, Key::F];
+
+            assert_eq!(keys, expected.iter().map(|&key| Some(key)).collect::<Vec<_>>());
+        }
+
+        assert_eq!(KeyboardLayout::Azerty.key('1'), Some(Key::One));
+        assert_eq!(KeyboardLayout::Qwerty.key_from_str("W"), Some(Key::Five));
+        assert_eq!(KeyboardLayout::Qwerty.key_from_str("Enter"), None);
+        assert_eq!(key_from_code("KeyX"), Some(Key::Zero));
+    }
+
+    #[test]
+    fn wait_needs_press_and_release() {
+        let mut keypad = Keypad::default();
+
+        keypad.press(Key::Five);
+        keypad.start_wait();
+        assert_eq!(keypad.poll_wait(), None);
+
+        // A key held before the wait counts once it's released.
+        keypad.release(Key::Five);
+        assert_eq!(keypad.poll_wait(), Some(Key::Five));
+        assert!(!keypad.is_waiting());
+
+        // Releasing a key that wasn't pressed during the wait doesn't end it.
+        keypad.press(Key::A);
+        keypad.start_wait();
+        keypad.press(Key::B);
+        keypad.release(Key::Six);
+        assert_eq!(keypad.poll_wait(), None);
+        keypad.release(Key::B);
+        assert_eq!(keypad.poll_wait(), Some(Key::B));
+    }
+}
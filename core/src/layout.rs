@@ -0,0 +1,194 @@
+//! Where the interpreter keeps its registers, stack and display.
+//!
+//! By default they are separate from the memory, which programs can't use to reach them. The
+//! COSMAC VIP interpreter kept them in the last page of its RAM instead, and some programs rely
+//! on it: they read the display, or patch registers with `FX55`. With [`MemoryLayout::Vip`],
+//! the memory at these addresses and the interpreter state are kept in sync, whichever of them
+//! an instruction modifies.
+//!
+//! The display is only mapped while it is 64x32, the only size the VIP had.
+
+use crate::program::Program;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryLayout {
+    /// Registers, stack and display aren't in the memory.
+    #[default]
+    Separate,
+    /// Registers, stack and display are at their addresses on the COSMAC VIP.
+    Vip,
+}
+
+/// Start of the stack, whose entries are stored big-endian from its end, downwards.
+pub const STACK: usize = 0xEA0;
+/// End of the stack, which has room for [`STACK_ENTRIES`] entries.
+pub const STACK_END: usize = 0xED0;
+pub const STACK_ENTRIES: usize = (STACK_END - STACK) / 2;
+/// V0 to VF.
+pub const REGISTERS: usize = 0xEF0;
+/// The 64x32 display, 8 bytes per row from top to bottom, the leftmost pixel of each byte
+/// being its most significant bit.
+pub const DISPLAY: usize = 0xF00;
+/// Memory needed by the layout.
+pub const END: usize = 0x1000;
+
+const DISPLAY_WIDTH: usize = 64;
+const DISPLAY_HEIGHT: usize = 32;
+const ROW_BYTES: usize = DISPLAY_WIDTH / 8;
+
+/// Bytes of the stack, registers and display, in that order.
+const MAPPED: usize = STACK_ENTRIES * 2 + 16 + DISPLAY_HEIGHT * ROW_BYTES;
+
+/// Contents of the mapped memory.
+pub(crate) type Image = [u8; MAPPED];
+
+/// Address of each byte of an [`Image`].
+fn address(index: usize) -> usize {
+    match index {
+        index if index < STACK_ENTRIES * 2 => STACK_END - STACK_ENTRIES * 2 + index,
+        index if index < STACK_ENTRIES * 2 + 16 => REGISTERS + index - STACK_ENTRIES * 2,
+        index => DISPLAY + index - STACK_ENTRIES * 2 - 16,
+    }
+}
+
+fn display_mapped(program: &Program) -> bool {
+    (program.screen.width(), program.screen.height()) == (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+}
+
+/// The mapped memory as the state of `program` says it should be.
+fn image(program: &Program) -> Image {
+    let mut image = [0; MAPPED];
+
+    for entry in 0..STACK_ENTRIES {
+        // Entry 0 is at the end of the stack.
+        let index = (STACK_ENTRIES - 1 - entry) * 2;
+        image[index..index + 2].copy_from_slice(&program.stack[entry].to_be_bytes());
+    }
+
+    let registers = STACK_ENTRIES * 2;
+    image[registers..registers + 16].copy_from_slice(&program.v);
+
+    if display_mapped(program) {
+        for y in 0..DISPLAY_HEIGHT {
+            for column in 0..ROW_BYTES {
+                image[registers + 16 + y * ROW_BYTES + column] = program.screen.byte(column, y);
+            }
+        }
+    } else {
+        for (index, byte) in image.iter_mut().enumerate().skip(registers + 16) {
+            *byte = program.memory[address(index)];
+        }
+    }
+
+    image
+}
+
+/// Update the registers, stack and display of `program` from its memory, returning what the
+/// memory contained.
+pub(crate) fn load(program: &mut Program) -> Image {
+    let mut image = [0; MAPPED];
+    for (index, byte) in image.iter_mut().enumerate() {
+        *byte = program.memory[address(index)];
+    }
+
+    for entry in 0..STACK_ENTRIES {
+        let index = (STACK_ENTRIES - 1 - entry) * 2;
+        program.stack[entry] = u16::from_be_bytes([image[index], image[index + 1]]);
+    }
+
+    let registers = STACK_ENTRIES * 2;
+    program.v.copy_from_slice(&image[registers..registers + 16]);
+
+    if display_mapped(program) {
+        for y in 0..DISPLAY_HEIGHT {
+            for column in 0..ROW_BYTES {
+                let byte = image[registers + 16 + y * ROW_BYTES + column];
+                program.screen.set_byte(column, y, byte);
+            }
+        }
+    }
+
+    image
+}
+
+/// Write to memory what changed in the state of `program` since [`load`] returned `before`,
+/// then load back what only changed in memory.
+pub(crate) fn sync(program: &mut Program, before: &Image) {
+    for (index, byte) in image(program).iter().enumerate() {
+        if *byte != before[index] {
+            program.poke(address(index), *byte);
+        }
+    }
+
+    load(program);
+}
+
+/// Write the registers, stack and display of `program` to its memory.
+pub(crate) fn store(program: &mut Program) {
+    for (index, byte) in image(program).iter().enumerate() {
+        program.poke(address(index), *byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmulatorConfig;
+    use crate::program::Register;
+    use crate::rom::LoadError;
+
+    fn vip(rom: &[u8]) -> Program<'static> {
+        let mut program = EmulatorConfig::vip().memory_layout(MemoryLayout::Vip).build().unwrap();
+        program.load(rom).unwrap();
+        program
+    }
+
+    #[test]
+    fn registers_in_memory() {
+        // v3 = 0x42, v0 = memory[0xEF3], v1 = 7, then v0 and v1 are stored over v3 and v4.
+        let mut program = vip(&[
+            0x63, 0x42, 0xAE, 0xF3, 0xF0, 0x65, 0x61, 0x07, 0xF1, 0x55, 0x12, 0x0A,
+        ]);
+        program.run_until_stopped(3);
+        assert_eq!(program.memory()[REGISTERS + 3], 0x42);
+        assert_eq!(program.register(Register::V0), 0x42);
+
+        program.run_until_stopped(2);
+        assert_eq!(program.register(Register::V4), 7);
+        assert_eq!(program.memory()[REGISTERS..REGISTERS + 5], [0x42, 0x07, 0, 0x42, 0x07]);
+
+        program.memory_mut()[REGISTERS + 0xF] = 9;
+        program.run();
+        assert_eq!(program.register(Register::VF), 9);
+
+        program.set_register(Register::V5, 5);
+        assert_eq!(program.memory()[REGISTERS + 5], 5);
+    }
+
+    #[test]
+    fn stack_and_display_in_memory() {
+        // Call 0x206, which writes 0xF0 to the first byte of the display.
+        let mut program = vip(&[
+            0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0xAF, 0x00, 0x60, 0xF0, 0xF0, 0x55, 0x12, 0x0C,
+        ]);
+        program.run_until_stopped(4);
+
+        assert_eq!(program.memory()[STACK_END - 2..STACK_END], [0x02, 0x02]);
+        assert!(program.screen.get(0, 0) && program.screen.get(3, 0) && !program.screen.get(4, 0));
+
+        program.memory_mut()[DISPLAY + 8 * 31 + 7] = 0x01;
+        program.run();
+        assert!(program.screen.get(63, 31));
+    }
+
+    #[test]
+    fn rom_ends_before_the_stack() {
+        let mut program = vip(&[0x00, 0xE0]);
+
+        assert!(matches!(
+            program.load(&[0; STACK - 0x1FF]),
+            Err(LoadError::TooLarge { size: 0xCA1, available: 0xCA0 })
+        ));
+        assert!(program.load(&[0; STACK - 0x200]).is_ok());
+    }
+}
@@ -1,5 +1,52 @@
+//! CHIP-8 interpreter and the machines around it.
+//!
+//! The crate is `no_std` and doesn't allocate by default, running programs only needs
+//! [`program`] and the modules it depends on, the host providing randomness and receiving timer
+//! ticks and frames through [`hooks`]. The `alloc` feature adds everything needing a heap, and
+//! `std` loading ROMs from files and seeding the random generator from the system.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+pub mod analysis;
+pub mod bus;
+pub mod cdp1802;
+pub mod checksum;
+pub mod chip8x;
+pub mod config;
+pub mod display;
+#[cfg(feature = "alloc")]
+pub mod filter;
+pub mod font;
+#[cfg(feature = "std")]
+pub mod gif;
+pub mod hooks;
 pub mod instructions;
+pub mod keypad;
+pub mod layout;
+pub mod machine;
+#[cfg(feature = "alloc")]
+pub mod megachip;
+pub mod platform;
 pub mod program;
+pub mod quirks;
+#[cfg(feature = "alloc")]
+pub mod recompiler;
+pub mod render;
+pub mod rom;
+#[cfg(feature = "alloc")]
+pub mod scale;
+#[cfg(feature = "alloc")]
+pub mod screenshot;
+pub mod state;
+pub mod sys;
+pub mod timing;
+#[cfg(feature = "alloc")]
+pub mod translate;
+pub mod vip;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,132 @@
+//! Inspection shared by the interpreters, so that they can be run side by side and compared.
+//!
+//! [`Program`] interprets CHIP-8 instructions directly, [`Vip`] runs the original interpreter
+//! on an emulated COSMAC VIP: with the same ROM and inputs, their [`Snapshot`]s taken at the end
+//! of a frame should match.
+
+use crate::display::Display;
+use crate::keypad::{ Key, Keypad };
+use crate::program::{ Program, Register };
+use crate::state::ExecutionState;
+use crate::vip::Vip;
+
+/// State of a CHIP-8 machine visible to programs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub screen: Display,
+}
+
+pub trait Machine {
+    /// The memory programs can address.
+    fn memory(&self) -> &[u8];
+    fn screen(&self) -> &Display;
+    fn keypad(&self) -> &Keypad;
+    fn keydown(&mut self, key: Key);
+    fn keyup(&mut self, key: Key);
+    fn register(&self, x: Register) -> u8;
+    fn i(&self) -> u16;
+    fn delay_timer(&self) -> u8;
+    fn sound_timer(&self) -> u8;
+    /// Run the machine for one 60 Hz frame.
+    fn run_frame(&mut self) -> ExecutionState;
+
+    fn snapshot(&self) -> Snapshot {
+        let registers = Register::ALL.map(|x| self.register(x));
+
+        Snapshot {
+            registers,
+            i: self.i(),
+            delay_timer: self.delay_timer(),
+            sound_timer: self.sound_timer(),
+            screen: *self.screen(),
+        }
+    }
+}
+
+impl Machine for Program<'_> {
+    fn memory(&self) -> &[u8] {
+        Program::memory(self)
+    }
+
+    fn screen(&self) -> &Display {
+        &self.screen
+    }
+
+    fn keypad(&self) -> &Keypad {
+        Program::keypad(self)
+    }
+
+    fn keydown(&mut self, key: Key) {
+        Program::keydown(self, key)
+    }
+
+    fn keyup(&mut self, key: Key) {
+        Program::keyup(self, key)
+    }
+
+    fn register(&self, x: Register) -> u8 {
+        Program::register(self, x)
+    }
+
+    fn i(&self) -> u16 {
+        Program::i(self)
+    }
+
+    fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    fn run_frame(&mut self) -> ExecutionState {
+        Program::run_frame(self)
+    }
+}
+
+impl Machine for Vip {
+    fn memory(&self) -> &[u8] {
+        Vip::memory(self)
+    }
+
+    fn screen(&self) -> &Display {
+        Vip::screen(self)
+    }
+
+    fn keypad(&self) -> &Keypad {
+        Vip::keypad(self)
+    }
+
+    fn keydown(&mut self, key: Key) {
+        Vip::keydown(self, key)
+    }
+
+    fn keyup(&mut self, key: Key) {
+        Vip::keyup(self, key)
+    }
+
+    fn register(&self, x: Register) -> u8 {
+        Vip::register(self, x)
+    }
+
+    fn i(&self) -> u16 {
+        Vip::i(self)
+    }
+
+    fn delay_timer(&self) -> u8 {
+        Vip::delay_timer(self)
+    }
+
+    fn sound_timer(&self) -> u8 {
+        Vip::sound_timer(self)
+    }
+
+    fn run_frame(&mut self) -> ExecutionState {
+        Vip::run_frame(self)
+    }
+}
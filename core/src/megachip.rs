@@ -0,0 +1,672 @@
+//! MegaChip, the 256x192 colour extension of SCHIP.
+//!
+//! A [`MegaChip`] runs on top of a [`Program`], which keeps the registers, stack, timers and
+//! keypad and executes the instructions that only touch those. Everything involving memory or
+//! the display goes through the decoder table of this module instead, since MegaChip ROMs are
+//! addressed with a 24-bit I and draw in colour.
+//!
+//! Until `0011` enables MegaChip mode, programs run as SCHIP programs on the monochrome screen of
+//! the [`Program`].
+
+use crate::checksum;
+use crate::config::{ ConfigError, EmulatorConfig };
+use crate::instructions::Instruction;
+use crate::platform::Platform;
+use crate::program::{ Cursor, Program };
+use crate::render::{ Palette, Renderer };
+use crate::rom::{ LoadError, LoadInfo };
+use crate::state::{ ExecutionState, Fault };
+
+use alloc::{ vec, vec::Vec };
+use core::num::NonZeroUsize;
+
+/// Width of the display in MegaChip mode.
+pub const WIDTH: usize = 256;
+/// Height of the display in MegaChip mode.
+pub const HEIGHT: usize = 192;
+/// Memory addressable with the 24-bit I.
+pub const MEMORY_SIZE: usize = 0x100_0000;
+
+const START_ADDRESS: usize = 0x200;
+
+/// How sprite pixels are combined with the pixels under them, set by `080N`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    /// The sprite is drawn with 25% opacity.
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    Additive,
+    Multiply,
+}
+
+impl BlendMode {
+    fn from_nibble(value: u16) -> Option<Self> {
+        let mode = match value {
+            0 => BlendMode::Normal,
+            1 => BlendMode::Alpha25,
+            2 => BlendMode::Alpha50,
+            3 => BlendMode::Alpha75,
+            4 => BlendMode::Additive,
+            5 => BlendMode::Multiply,
+            _ => return None,
+        };
+
+        Some(mode)
+    }
+
+    /// Combine the ARGB colours `under` and `over`.
+    fn blend(self, under: u32, over: u32) -> u32 {
+        let channel = |color: u32, shift: u32| (color >> shift) & 0xFF;
+        let mix = |f: &dyn Fn(u32, u32) -> u32| {
+            [16, 8, 0].iter().fold(0xFF00_0000, |color, &shift| {
+                color | (f(channel(under, shift), channel(over, shift)).min(0xFF) << shift)
+            })
+        };
+        let alpha = |opacity: u32| mix(&|a, b| (a * (4 - opacity) + b * opacity) / 4);
+
+        match self {
+            BlendMode::Normal => over,
+            BlendMode::Alpha25 => alpha(1),
+            BlendMode::Alpha50 => alpha(2),
+            BlendMode::Alpha75 => alpha(3),
+            BlendMode::Additive => mix(&|a, b| a + b),
+            BlendMode::Multiply => mix(&|a, b| a * b / 0xFF),
+        }
+    }
+}
+
+/// Digitised sound started by `060N`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Samples per second.
+    pub rate: u16,
+    /// Address of the first sample, right after the 6 bytes header.
+    pub address: usize,
+    pub length: usize,
+    pub looping: bool,
+}
+
+type Handler = fn(&mut MegaChip, u16) -> Cursor;
+
+/// Handlers of the instructions, indexed by their highest nibble.
+const DECODER: [Handler; 16] = [
+    MegaChip::system, MegaChip::delegate, MegaChip::delegate, MegaChip::delegate,
+    MegaChip::delegate, MegaChip::delegate, MegaChip::delegate, MegaChip::delegate,
+    MegaChip::delegate, MegaChip::delegate, MegaChip::set_i, MegaChip::delegate,
+    MegaChip::delegate, MegaChip::draw, MegaChip::delegate, MegaChip::misc,
+];
+
+pub struct MegaChip {
+    program: Program<'static>,
+    memory: Vec<u8>,
+    i: usize,
+    /// Whether MegaChip mode is on, switched by `0011` and `0010`.
+    enabled: bool,
+    /// ARGB colours, index 0 being transparent.
+    palette: [u32; 256],
+    sprite_width: usize,
+    sprite_height: usize,
+    alpha: u8,
+    blend_mode: BlendMode,
+    collision_index: u8,
+    /// Palette indices of the pixels being drawn, to detect collisions.
+    indices: Vec<u8>,
+    /// ARGB pixels being drawn, shown by the next `00E0`.
+    back: Vec<u32>,
+    /// ARGB pixels on screen.
+    front: Vec<u32>,
+    sample: Option<Sample>,
+    /// SCHIP RPL user flags, saved by `FX75`.
+    flags: [u8; 16],
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        MegaChip::with_config(EmulatorConfig::new()).expect("default configuration is valid")
+    }
+
+    /// A MegaChip machine around the program described by `config`, whose platform is set to
+    /// [`Platform::MegaChip8`]. Its memory size and start address are ignored, ROMs being
+    /// loaded at 0x200 in 16 MiB of memory.
+    pub fn with_config(config: EmulatorConfig) -> Result<Self, ConfigError> {
+        let program = config.platform(Platform::MegaChip8).build()?;
+
+        let mut megachip = MegaChip {
+            program,
+            memory: vec![0; MEMORY_SIZE],
+            i: 0,
+            enabled: false,
+            palette: [0; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend_mode: BlendMode::Normal,
+            collision_index: 0,
+            indices: vec![0; WIDTH * HEIGHT],
+            back: vec![0; WIDTH * HEIGHT],
+            front: vec![0; WIDTH * HEIGHT],
+            sample: None,
+            flags: [0; 16],
+        };
+        megachip.reset_memory();
+
+        Ok(megachip)
+    }
+
+    /// Clear the memory, keeping the fonts of the program.
+    fn reset_memory(&mut self) {
+        self.memory.fill(0);
+
+        let fonts = &self.program.memory()[..START_ADDRESS];
+        self.memory[..START_ADDRESS].copy_from_slice(fonts);
+    }
+
+    /// Clear everything a program can change but the memory and the RPL user flags, which are
+    /// meant to outlive programs.
+    fn reset_state(&mut self) {
+        self.program.reset_state();
+        self.i = 0;
+        self.enabled = false;
+        self.palette = [0; 256];
+        self.sprite_width = 0;
+        self.sprite_height = 0;
+        self.alpha = 0xFF;
+        self.blend_mode = BlendMode::Normal;
+        self.collision_index = 0;
+        self.indices.fill(0);
+        self.back.fill(0);
+        self.front.fill(0);
+        self.sample = None;
+    }
+
+    /// Load `rom` at 0x200 and start executing from there, resetting everything else as
+    /// [`Program::load_at`] does.
+    pub fn load(&mut self, rom: &[u8]) -> Result<LoadInfo, LoadError> {
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+
+        if rom.len() > MEMORY_SIZE - START_ADDRESS {
+            let available = MEMORY_SIZE - START_ADDRESS;
+            return Err(LoadError::TooLarge { size: rom.len(), available });
+        }
+
+        self.reset_state();
+        self.reset_memory();
+        self.memory[START_ADDRESS..START_ADDRESS + rom.len()].copy_from_slice(rom);
+        self.program.program_counter = START_ADDRESS as u16;
+
+        Ok(LoadInfo {
+            address: START_ADDRESS as u16,
+            size: rom.len(),
+            crc32: checksum::crc32(rom),
+            sha1: checksum::sha1(rom)
+        })
+    }
+
+    /// Execute the instruction at the program counter, unless the program is halted or faulted.
+    pub fn run(&mut self) -> ExecutionState {
+        if self.program.state.is_stopped() {
+            return self.program.state;
+        }
+
+        let address = self.program.program_counter;
+        let cursor = match self.word(address as usize) {
+            Some(code) => DECODER[(code >> 12) as usize](self, code),
+            None => self.out_of_bounds(address as usize + 1),
+        };
+
+        self.program.advance(cursor)
+    }
+
+    /// Execute the instructions of one frame, then decrement the timers.
+    pub fn run_frame(&mut self) -> ExecutionState {
+        for _ in 0..self.program.config().get_instructions_per_frame() {
+            if self.run().is_stopped() {
+                break;
+            }
+        }
+
+        self.program.decrement_timers();
+        self.program.state
+    }
+
+    pub fn state(&self) -> ExecutionState {
+        self.program.state
+    }
+
+    /// The program holding the registers, timers and keypad.
+    pub fn program(&self) -> &Program<'static> {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Program<'static> {
+        &mut self.program
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    /// Whether MegaChip mode is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn palette(&self) -> &[u32; 256] {
+        &self.palette
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Opacity of the whole screen set by `05NN`, which games use to fade in and out.
+    pub fn alpha(&self) -> u8 {
+        self.alpha
+    }
+
+    /// The sample being played, if any.
+    pub fn sample(&self) -> Option<Sample> {
+        self.sample
+    }
+
+    /// Unsigned 8-bit samples of the sample being played.
+    pub fn sample_data(&self) -> Option<&[u8]> {
+        self.sample.map(|sample| &self.memory[sample.address..sample.address + sample.length])
+    }
+
+    /// Size of the display in the current mode.
+    pub fn dimensions(&self) -> (usize, usize) {
+        if self.enabled {
+            (WIDTH, HEIGHT)
+        } else {
+            (self.program.screen.width(), self.program.screen.height())
+        }
+    }
+
+    /// RGBA pixels of the display, the monochrome screen being drawn with `palette` outside of
+    /// MegaChip mode.
+    pub fn to_rgba(&self, palette: Palette) -> Vec<u8> {
+        if !self.enabled {
+            return Renderer::new(NonZeroUsize::MIN, palette).render(&self.program.screen);
+        }
+
+        self.front.iter()
+            .flat_map(|color| {
+                let [_, r, g, b] = color.to_be_bytes();
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    fn word(&self, address: usize) -> Option<u16> {
+        let bytes = self.memory.get(address..address + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn out_of_bounds(&self, access: usize) -> Cursor {
+        Cursor::Fault(Fault::MemoryOutOfBounds { address: self.program.program_counter, access })
+    }
+
+    fn invalid(&self, opcode: u16) -> Cursor {
+        Cursor::Fault(Fault::InvalidInstruction { address: self.program.program_counter, opcode })
+    }
+
+    /// Run an instruction that only uses the registers, stack, timers or keypad.
+    fn delegate(&mut self, code: u16) -> Cursor {
+        Instruction::from(code).run(&mut self.program)
+    }
+
+    fn system(&mut self, code: u16) -> Cursor {
+        let nn = (code & 0xFF) as usize;
+
+        match code {
+            0x0010 => {
+                self.enabled = false;
+                self.program.screen.clear();
+            },
+            0x0011 => {
+                self.enabled = true;
+                self.clear_buffers();
+                self.front.fill(0);
+            },
+            0x0100..=0x01FF => {
+                let low = match self.word(self.program.program_counter as usize + 2) {
+                    Some(low) => low as usize,
+                    None => return self.out_of_bounds(self.program.program_counter as usize + 3),
+                };
+
+                self.i = (nn << 16) | low;
+                return Cursor::Skip;
+            },
+            0x0200..=0x02FF => {
+                if self.i + nn * 4 > self.memory.len() {
+                    return self.out_of_bounds(self.i + nn * 4 - 1);
+                }
+
+                for index in 0..nn {
+                    let at = self.i + index * 4;
+                    let argb = &self.memory[at..at + 4];
+                    self.palette[index + 1] = argb.iter()
+                        .fold(0, |color, &byte| color << 8 | byte as u32);
+                }
+            },
+            0x0300..=0x03FF => self.sprite_width = if nn == 0 { 256 } else { nn },
+            0x0400..=0x04FF => self.sprite_height = if nn == 0 { 256 } else { nn },
+            0x0500..=0x05FF => self.alpha = nn as u8,
+            0x0600..=0x060F => {
+                let header = match self.memory.get(self.i..self.i + 6) {
+                    Some(header) => header,
+                    None => return self.out_of_bounds(self.i + 5),
+                };
+
+                let rate = u16::from_be_bytes([header[0], header[1]]);
+                let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+                let address = self.i + 6;
+
+                if address + length > self.memory.len() {
+                    return self.out_of_bounds(address + length - 1);
+                }
+
+                self.sample = Some(Sample { rate, address, length, looping: code & 0xF == 0 });
+            },
+            0x0700 => self.sample = None,
+            0x0800..=0x080F => match BlendMode::from_nibble(code & 0xF) {
+                Some(mode) => self.blend_mode = mode,
+                None => return self.invalid(code),
+            },
+            0x0900..=0x09FF => self.collision_index = nn as u8,
+            0x00B0..=0x00BF if self.enabled => self.scroll(0, -((code & 0xF) as isize)),
+            0x00C0..=0x00CF => self.scroll(0, (code & 0xF) as isize),
+            0x00FB => self.scroll(4, 0),
+            0x00FC => self.scroll(-4, 0),
+            0x00E0 if self.enabled => {
+                self.front.copy_from_slice(&self.back);
+                self.clear_buffers();
+            },
+            0x00FE if !self.enabled => self.program.screen.resize(64, 32),
+            0x00FF if !self.enabled => self.program.screen.resize(128, 64),
+            0x00FE | 0x00FF => {},
+            _ => return self.delegate(code),
+        }
+
+        Cursor::Next
+    }
+
+    fn clear_buffers(&mut self) {
+        self.back.fill(0);
+        self.indices.fill(0);
+    }
+
+    /// Move the content of the display by `dx` and `dy` pixels, in the current mode.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let source = |x: usize, y: usize, width: usize, height: usize| {
+            let (x, y) = (x as isize - dx, y as isize - dy);
+
+            if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+                None
+            } else {
+                Some((x as usize, y as usize))
+            }
+        };
+
+        if self.enabled {
+            let (back, indices) = (self.back.clone(), self.indices.clone());
+
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let from = source(x, y, WIDTH, HEIGHT).map(|(x, y)| y * WIDTH + x);
+                    self.back[y * WIDTH + x] = from.map_or(0, |from| back[from]);
+                    self.indices[y * WIDTH + x] = from.map_or(0, |from| indices[from]);
+                }
+            }
+        } else {
+            let screen = self.program.screen;
+            let (width, height) = (screen.width(), screen.height());
+
+            for y in 0..height {
+                for x in 0..width {
+                    let lit = source(x, y, width, height).is_some_and(|(x, y)| screen.get(x, y));
+                    self.program.screen.set(x, y, lit);
+                }
+            }
+        }
+    }
+
+    fn set_i(&mut self, code: u16) -> Cursor {
+        self.i = (code & 0xFFF) as usize;
+        Cursor::Next
+    }
+
+    fn draw(&mut self, code: u16) -> Cursor {
+        let vx = self.program.v[(code >> 8) as usize & 0xF] as usize;
+        let vy = self.program.v[(code >> 4) as usize & 0xF] as usize;
+        let n = (code & 0xF) as usize;
+
+        // Font glyphs are drawn as monochrome sprites, even in MegaChip mode.
+        if !self.enabled || self.i < START_ADDRESS {
+            return self.draw_monochrome(vx, vy, n);
+        }
+
+        let (width, height) = (self.sprite_width, self.sprite_height);
+        if self.i + width * height > self.memory.len() {
+            return self.out_of_bounds(self.i + width * height - 1);
+        }
+
+        self.program.v[0xF] = 0;
+
+        for row in 0..height {
+            for column in 0..width {
+                let (x, y) = (vx + column, vy + row);
+                let index = self.memory[self.i + row * width + column];
+
+                if index == 0 || x >= WIDTH || y >= HEIGHT {
+                    continue;
+                }
+
+                let pixel = y * WIDTH + x;
+                if self.indices[pixel] == self.collision_index && self.indices[pixel] != 0 {
+                    self.program.v[0xF] = 1;
+                }
+
+                self.indices[pixel] = index;
+                let color = self.palette[index as usize];
+                self.back[pixel] = self.blend_mode.blend(self.back[pixel], color);
+            }
+        }
+
+        Cursor::Next
+    }
+
+    /// Draw a SCHIP sprite, 16x16 when `n` is 0, clipped at the edges of the screen.
+    fn draw_monochrome(&mut self, vx: usize, vy: usize, n: usize) -> Cursor {
+        let (columns, rows) = if n == 0 { (16, 16) } else { (8, n) };
+        let bytes = columns / 8 * rows;
+
+        if self.i + bytes > self.memory.len() {
+            return self.out_of_bounds(self.i + bytes - 1);
+        }
+
+        if self.enabled {
+            // Outside of the colour buffers, glyphs are drawn in white.
+            return self.draw_glyph(vx, vy, rows);
+        }
+
+        let screen = &mut self.program.screen;
+        let (width, height) = (screen.width(), screen.height());
+        let (left, top) = (vx % width, vy % height);
+        let mut collision = 0;
+
+        for row in 0..rows.min(height - top) {
+            for column in 0..columns.min(width - left) {
+                let byte = self.memory[self.i + row * columns / 8 + column / 8];
+
+                if (byte >> (7 - column % 8)) & 1 == 1 && screen.toggle(left + column, top + row) {
+                    collision = 1;
+                }
+            }
+        }
+
+        self.program.v[0xF] = collision;
+        Cursor::Next
+    }
+
+    fn draw_glyph(&mut self, vx: usize, vy: usize, rows: usize) -> Cursor {
+        self.program.v[0xF] = 0;
+
+        for row in 0..rows {
+            let byte = self.memory[self.i + row];
+
+            for column in 0..8 {
+                let (x, y) = (vx + column, vy + row);
+
+                if (byte >> (7 - column)) & 1 == 1 && x < WIDTH && y < HEIGHT {
+                    self.back[y * WIDTH + x] = 0xFFFF_FFFF;
+                }
+            }
+        }
+
+        Cursor::Next
+    }
+
+    fn misc(&mut self, code: u16) -> Cursor {
+        let x = (code >> 8) as usize & 0xF;
+        let vx = self.program.v[x];
+
+        match code & 0xFF {
+            0x1E => self.i += vx as usize,
+            0x29 => {
+                let font = self.program.config().get_font_address() as usize;
+                self.i = font + (vx & 0xF) as usize * 5;
+            },
+            0x30 => {
+                let font = self.program.config().get_large_font_address() as usize;
+                self.i = font + (vx & 0xF) as usize * 10;
+            },
+            0x33 => {
+                if self.i + 3 > self.memory.len() {
+                    return self.out_of_bounds(self.i + 2);
+                }
+
+                self.memory[self.i] = vx / 100;
+                self.memory[self.i + 1] = vx / 10 % 10;
+                self.memory[self.i + 2] = vx % 10;
+            },
+            0x55 | 0x65 => {
+                if self.i + x + 1 > self.memory.len() {
+                    return self.out_of_bounds(self.i + x);
+                }
+
+                for register in 0..=x {
+                    if code & 0xFF == 0x55 {
+                        self.memory[self.i + register] = self.program.v[register];
+                    } else {
+                        self.program.v[register] = self.memory[self.i + register];
+                    }
+                }
+
+                let quirks = self.program.quirks();
+                if !quirks.memory_leave_i_unchanged {
+                    self.i += if quirks.memory_increment_by_x { x } else { x + 1 };
+                }
+            },
+            0x75 => self.flags[..=x].copy_from_slice(&self.program.v[..=x]),
+            0x85 => self.program.v[..=x].copy_from_slice(&self.flags[..=x]),
+            _ => return self.delegate(code),
+        }
+
+        Cursor::Next
+    }
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        MegaChip::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Register;
+
+    #[test]
+    fn colour_sprites() {
+        let mut rom = vec![
+            0x00, 0x11,             // MegaChip mode
+            0x01, 0x00, 0x02, 0x20, // I = 0x000220
+            0x02, 0x02,             // load 2 colours
+            0x01, 0x00, 0x02, 0x28, // I = 0x000228
+            0x03, 0x02, 0x04, 0x01, // 2x1 sprites
+            0x09, 0x02,             // collide with colour 2
+            0xD0, 0x00,             // draw at (0, 0)
+            0xD0, 0x00,             // draw again, colliding
+            0x00, 0xE0,             // show the frame
+        ];
+        rom.resize(0x20, 0);
+        rom.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x01, 0x02]);
+
+        let mut megachip = MegaChip::new();
+        megachip.load(&rom).unwrap();
+        for _ in 0..10 {
+            megachip.run();
+        }
+
+        assert_eq!(megachip.state(), ExecutionState::Running);
+        assert!(megachip.is_enabled());
+        assert_eq!(megachip.program().register(Register::VF), 1);
+        assert_eq!(megachip.dimensions(), (256, 192));
+        let rgba = megachip.to_rgba(Palette::DEFAULT);
+        assert_eq!(rgba[..8], [0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn reload_after_halt() {
+        // MegaChip mode, v0 = 5, I = 0x000300, 2x1 sprites, then exit.
+        let mut megachip = MegaChip::new();
+        megachip.load(&[
+            0x00, 0x11, 0x60, 0x05, 0x01, 0x00, 0x03, 0x00, 0x03, 0x02, 0x04, 0x01, 0x00, 0xFD,
+        ]).unwrap();
+        for _ in 0..6 {
+            megachip.run();
+        }
+        assert!(megachip.state().is_stopped());
+
+        megachip.load(&[0x61, 0x07]).unwrap();
+        assert_eq!(megachip.state(), ExecutionState::Running);
+        assert!(!megachip.is_enabled());
+        assert_eq!((megachip.program().register(Register::V0), megachip.i()), (0, 0));
+        assert_eq!((megachip.sprite_width, megachip.sprite_height), (0, 0));
+        assert_eq!(megachip.dimensions(), (64, 32));
+
+        assert_eq!(megachip.run(), ExecutionState::Running);
+        assert_eq!(megachip.program().register(Register::V1), 7);
+    }
+
+    #[test]
+    fn samples_and_blending() {
+        let mut megachip = MegaChip::new();
+        megachip.load(&[0x01, 0x00, 0x10, 0x00, 0x06, 0x01]).unwrap();
+        let header = [0x1F, 0x40, 0x00, 0x00, 0x03, 0x00];
+        megachip.memory[0x1000..0x1006].copy_from_slice(&header);
+        megachip.memory[0x1006..0x1009].copy_from_slice(&[0x80, 0x90, 0xA0]);
+
+        megachip.run();
+        megachip.run();
+
+        let sample = Sample { rate: 8000, address: 0x1006, length: 3, looping: false };
+        assert_eq!(megachip.sample(), Some(sample));
+        assert_eq!(megachip.sample_data(), Some(&[0x80, 0x90, 0xA0][..]));
+
+        assert_eq!(BlendMode::Alpha50.blend(0xFF00_0000, 0xFFFF_FF00), 0xFF7F_7F00);
+        assert_eq!(BlendMode::Additive.blend(0xFF80_8080, 0xFF80_0000), 0xFFFF_8080);
+    }
+}
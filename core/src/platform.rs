@@ -0,0 +1,134 @@
+//! Machines and interpreters CHIP-8 programs were written for.
+
+use crate::quirks::Quirks;
+
+use core::fmt;
+
+/// Platforms of the community CHIP-8 database, along with hires CHIP-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// The interpreter of the COSMAC VIP.
+    OriginalChip8,
+    /// CHIP-8 programs calling machine code routines of the COSMAC VIP.
+    HybridVip,
+    /// CHIP-8 with the 64x64 display of the patched COSMAC VIP interpreter.
+    HiresChip8,
+    /// CHIP-8 as implemented by most modern interpreters.
+    ModernChip8,
+    Chip8X,
+    Chip48,
+    Superchip1,
+    /// SCHIP 1.1, with the behaviour of modern interpreters.
+    Superchip,
+    MegaChip8,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 10] = [
+        Platform::OriginalChip8, Platform::HybridVip, Platform::HiresChip8, Platform::ModernChip8,
+        Platform::Chip8X, Platform::Chip48, Platform::Superchip1, Platform::Superchip,
+        Platform::MegaChip8, Platform::XoChip,
+    ];
+
+    /// Identifier in the community database, which doesn't list hires CHIP-8 as a platform of
+    /// its own.
+    pub fn id(self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "originalChip8",
+            Platform::HybridVip => "hybridVIP",
+            Platform::HiresChip8 => "chip8Hires",
+            Platform::ModernChip8 => "modernChip8",
+            Platform::Chip8X => "chip8x",
+            Platform::Chip48 => "chip48",
+            Platform::Superchip1 => "superchip1",
+            Platform::Superchip => "superchip",
+            Platform::MegaChip8 => "megachip8",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Platform::ALL.iter().copied().find(|platform| platform.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::OriginalChip8 => "CHIP-8 (COSMAC VIP)",
+            Platform::HybridVip => "CHIP-8 with machine code (COSMAC VIP)",
+            Platform::HiresChip8 => "CHIP-8 hires (COSMAC VIP)",
+            Platform::ModernChip8 => "CHIP-8",
+            Platform::Chip8X => "CHIP-8X",
+            Platform::Chip48 => "CHIP-48",
+            Platform::Superchip1 => "SCHIP 1.0",
+            Platform::Superchip => "SCHIP 1.1",
+            Platform::MegaChip8 => "MegaChip",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    /// Size of the display, in pixels.
+    pub fn display_size(self) -> (usize, usize) {
+        match self {
+            Platform::HiresChip8 => (64, 64),
+            _ => (64, 32),
+        }
+    }
+
+    /// Address where programs are loaded: the CHIP-8X interpreter was larger than the VIP one,
+    /// pushing its programs to 0x300.
+    pub fn start_address(self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+
+    /// Quirks of the platform, as listed by the community database.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::OriginalChip8 | Platform::HybridVip | Platform::HiresChip8
+            | Platform::Chip8X => {
+                Quirks { vblank: true, logic: true, ..Quirks::NONE }
+            },
+            Platform::ModernChip8 => Quirks::NONE,
+            Platform::Chip48 => {
+                Quirks { shift: true, memory_increment_by_x: true, jump: true, ..Quirks::NONE }
+            },
+            // SCHIP 1.0 still incremented I by X on FX55 and FX65, as CHIP-48 did.
+            Platform::Superchip1 => {
+                Quirks { shift: true, memory_increment_by_x: true, jump: true, ..Quirks::NONE }
+            },
+            Platform::Superchip | Platform::MegaChip8 => {
+                Quirks { shift: true, memory_leave_i_unchanged: true, jump: true, ..Quirks::NONE }
+            },
+            Platform::XoChip => Quirks { wrap: true, ..Quirks::NONE },
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schip_versions() {
+        let (schip1, schip) = (Platform::Superchip1.quirks(), Platform::Superchip.quirks());
+
+        assert!(schip1.memory_increment_by_x && !schip1.memory_leave_i_unchanged);
+        assert!(!schip.memory_increment_by_x && schip.memory_leave_i_unchanged);
+        assert_eq!(schip1, Platform::Chip48.quirks());
+    }
+
+    #[test]
+    fn start_addresses() {
+        assert_eq!(Platform::Chip8X.start_address(), 0x300);
+        assert_eq!(Platform::OriginalChip8.start_address(), 0x200);
+    }
+}
@@ -1,12 +1,42 @@
+#[cfg(feature = "alloc")]
+use crate::bus::BusHook;
+use crate::bus::{ AccessCounters, AddressPolicy, BusState };
+use crate::checksum;
+use crate::chip8x::ColorLayer;
+use crate::config::{ ConfigError, EmulatorConfig, MAX_STACK_DEPTH };
+use crate::display::Display;
+use crate::font::Font;
+use crate::hooks::{ Hooks, XorShift };
 use crate::instructions::Instruction;
+use crate::keypad::{ Key, Keypad };
+use crate::layout::{ self, MemoryLayout };
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+#[cfg(feature = "alloc")]
+use crate::recompiler::{ self, Blocks, Engine };
+use crate::rom::{ LoadError, LoadInfo };
+use crate::state::{ ExecutionState, Fault, HaltReason };
+#[cfg(feature = "alloc")]
+use crate::sys::SysHandler;
+use crate::sys::SysPolicy;
+use crate::timing::{ self, Timing };
 
-use std::convert::TryFrom;
-use std::path::PathBuf;
-use std::fs::File;
-use std::io::{ self, Read };
+#[cfg(feature = "alloc")]
+use alloc::{ boxed::Box, vec, vec::Vec };
 
-use rand::rngs::ThreadRng;
+use core::convert::TryFrom;
+use core::error::Error;
+use core::fmt;
+use core::ops::{ Deref, DerefMut };
+#[cfg(feature = "alloc")]
+use core::ops::Range;
 
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Default font, see [`Font::Octo`](crate::font::Font::Octo).
 pub const SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0],
     [0x20, 0x60, 0x20, 0x20, 0x70],
@@ -30,86 +60,689 @@ pub enum Cursor {
     Stay,
     Next,
     Skip,
-    Jump(u16)
+    Jump(u16),
+    Halt(HaltReason),
+    Fault(Fault)
+}
+
+/// One of the sixteen registers, V0 to VF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Register {
+    V0 = 0x0,
+    V1 = 0x1,
+    V2 = 0x2,
+    V3 = 0x3,
+    V4 = 0x4,
+    V5 = 0x5,
+    V6 = 0x6,
+    V7 = 0x7,
+    V8 = 0x8,
+    V9 = 0x9,
+    VA = 0xA,
+    VB = 0xB,
+    VC = 0xC,
+    VD = 0xD,
+    VE = 0xE,
+    VF = 0xF,
+}
+
+impl Register {
+    /// Every register, ordered by index.
+    pub const ALL: [Register; 16] = [
+        Register::V0, Register::V1, Register::V2, Register::V3,
+        Register::V4, Register::V5, Register::V6, Register::V7,
+        Register::V8, Register::V9, Register::VA, Register::VB,
+        Register::VC, Register::VD, Register::VE, Register::VF,
+    ];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl TryFrom<u8> for Register {
+    type Error = InvalidRegister;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Register::try_from(value as usize)
+    }
+}
+
+impl TryFrom<usize> for Register {
+    type Error = InvalidRegister;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        Register::ALL.get(value).copied().ok_or(InvalidRegister(value))
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{:X}", self.index())
+    }
+}
+
+/// Error returned when converting a value outside of `0x0..=0xF` into a [`Register`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidRegister(pub usize);
+
+impl fmt::Display for InvalidRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid register {:#x}, registers range from V0 to VF", self.0)
+    }
+}
+
+impl Error for InvalidRegister {}
+
+/// Memory of a [`Program`], lent by the caller or allocated with the program.
+pub(crate) enum Memory<'a> {
+    Borrowed(&'a mut [u8]),
+    #[cfg(feature = "alloc")]
+    Owned(Vec<u8>),
+}
+
+impl Deref for Memory<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Memory::Borrowed(memory) => memory,
+            #[cfg(feature = "alloc")]
+            Memory::Owned(memory) => memory,
+        }
+    }
+}
+
+impl DerefMut for Memory<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Memory::Borrowed(memory) => memory,
+            #[cfg(feature = "alloc")]
+            Memory::Owned(memory) => memory,
+        }
+    }
 }
 
-pub struct Program {
-    pub memory: [u8; 4096],
+/// A CHIP-8 machine running a program.
+///
+/// Its memory is either allocated with it, [`with_config`](Program::with_config), or lent by the
+/// caller, [`with_memory`](Program::with_memory), which is the only way without the `alloc`
+/// feature. In both cases it is as large as the configured memory size.
+pub struct Program<'a> {
+    pub(crate) memory: Memory<'a>,
     pub(crate) v: [u8; 16],
     pub(crate) i: u16,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub program_counter: u16,
     pub(crate) stack_pointer: u8,
-    pub(crate) keypad: [bool; 16],
-    pub screen: [[bool; 64]; 32],
-    pub(crate) stack: [u16; 16],
-    pub(crate) rng: ThreadRng,
+    pub(crate) keypad: Keypad,
+    /// Second keypad of the CHIP-8X, read by `EXF2` and `EXF5`.
+    pub(crate) second_keypad: Keypad,
+    pub screen: Display,
+    /// Colours of the screen on CHIP-8X.
+    pub colors: ColorLayer,
+    pub(crate) stack: [u16; MAX_STACK_DEPTH],
+    pub(crate) rng: XorShift,
+    pub(crate) hooks: Hooks,
+    pub(crate) state: ExecutionState,
+    #[cfg(feature = "alloc")]
+    pub(crate) sys_handler: Option<Box<dyn SysHandler>>,
+    pub(crate) sys_policy: SysPolicy,
+    pub(crate) bus: BusState,
+    /// Instructions decoded at each address, empty if the decode cache is disabled.
+    #[cfg(feature = "alloc")]
+    pub(crate) decoded: Vec<Option<Instruction>>,
+    #[cfg(feature = "alloc")]
+    pub(crate) blocks: Blocks,
+    pub(crate) config: EmulatorConfig,
+    /// Configuration the program was built with.
+    pub(crate) defaults: EmulatorConfig,
+    /// Whether a sprite was drawn since the last frame, for the `vblank` quirk.
+    pub(crate) frame_drawn: bool,
+    /// Machine cycles left in the current frame with [`Timing::Vip`], negative when the last
+    /// instruction of the previous frame overran it.
+    pub(crate) cycles: i64,
 }
 
-use std::iter::repeat;
-
-impl Program {
-    fn instruction(&self) -> Instruction {
+impl Program<'_> {
+    fn instruction(&mut self) -> Result<Instruction, Fault> {
         let counter = self.program_counter as usize;
-        let code = &self.memory[counter..=counter+1];
-        let code = ((code[0] as u16) << 8) | (code[1] as u16);
-        //let code = u16::from_be_bytes(<[u8; 2]>::try_from(code).unwrap());
+        #[cfg(feature = "alloc")]
+        if let Some(Some(instruction)) = self.decoded.get(counter) {
+            return Ok(*instruction);
+        }
 
-        Instruction::from(code)
+        let code = self.memory().get(counter..=counter+1).ok_or(Fault::MemoryOutOfBounds {
+            address: self.program_counter,
+            access: counter + 1
+        })?;
+        let instruction = Instruction::from(((code[0] as u16) << 8) | (code[1] as u16));
+
+        #[cfg(feature = "alloc")]
+        if let Some(decoded) = self.decoded.get_mut(counter) {
+            *decoded = Some(instruction);
+        }
+
+        Ok(instruction)
     }
 
-    pub fn run(&mut self) {
-        match self.instruction().run(self) {
+    /// Forget the instructions decoded and compiled from the byte at `address`.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        for address in address.saturating_sub(1)..=address {
+            if let Some(decoded) = self.decoded.get_mut(address) {
+                *decoded = None;
+            }
+        }
+
+        self.blocks.invalidate(address);
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    pub(crate) fn invalidate(&mut self, _: usize) {}
+
+    /// Write `value` at `address`, bypassing the bus.
+    pub(crate) fn poke(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+        self.invalidate(address);
+    }
+
+    /// Execute the instruction at the program counter, unless the program is halted or faulted.
+    pub fn run(&mut self) -> ExecutionState {
+        if self.state.is_stopped() {
+            return self.state;
+        }
+
+        if self.config.memory_layout == MemoryLayout::Vip {
+            let before = layout::load(self);
+            let state = self.execute();
+            layout::sync(self, &before);
+
+            return state;
+        }
+
+        self.execute()
+    }
+
+    fn execute(&mut self) -> ExecutionState {
+        let cursor = match self.instruction() {
+            Ok(instruction) => instruction.run(self),
+            Err(fault) => Cursor::Fault(fault)
+        };
+
+        self.advance(cursor)
+    }
+
+    /// Move the program counter, or stop the program, as told by an executed instruction.
+    pub(crate) fn advance(&mut self, cursor: Cursor) -> ExecutionState {
+        match cursor {
             Cursor::Stay => {},
-            Cursor::Next => self.program_counter += 2,
-            Cursor::Skip => self.program_counter += 4,
-            Cursor::Jump(address) => self.program_counter = address
+            // Only a 64 KiB memory lets the program counter wrap around.
+            Cursor::Next => self.program_counter = self.program_counter.wrapping_add(2),
+            Cursor::Skip => self.program_counter = self.program_counter.wrapping_add(4),
+            Cursor::Jump(address) => self.program_counter = address,
+            Cursor::Halt(reason) => self.state = ExecutionState::Halted(reason),
+            Cursor::Fault(fault) => self.state = ExecutionState::Faulted(fault)
         }
+
+        self.state
     }
 
-    pub fn new() -> Self {
-        let mut memory = [0u8; 4096];
+    /// Execute at most `limit` instructions, stopping early if the program halts or faults.
+    pub fn run_until_stopped(&mut self, limit: usize) -> ExecutionState {
+        #[cfg(feature = "alloc")]
+        if recompiler::enabled(self) {
+            return recompiler::run(self, limit);
+        }
 
-        let mut i = 0;
-        for sprit in &SPRITES {
-            for byte in sprit {
-                memory[i] = *byte;
-                i += 1;
+        for _ in 0..limit {
+            if self.run().is_stopped() {
+                break;
             }
         }
 
-        Program {
+        self.state
+    }
+
+    /// Execute the instructions of one 60 Hz frame, as many as the [`Timing`] of the program
+    /// allows, then decrement the timers.
+    pub fn run_frame(&mut self) -> ExecutionState {
+        match self.config.timing {
+            Timing::Instructions => {
+                self.run_until_stopped(self.config.instructions_per_frame);
+            },
+            Timing::Vip => self.run_vip_frame(),
+        }
+
+        self.decrement_timers();
+
+        if let Some(frame) = self.hooks.frame {
+            frame(&self.screen);
+        }
+
+        self.state
+    }
+
+    fn run_vip_frame(&mut self) {
+        self.cycles += (timing::VIP_CYCLES_PER_FRAME - timing::VIP_INTERRUPT_CYCLES) as i64;
+
+        while self.cycles > 0 && !self.state.is_stopped() {
+            let instruction = match self.instruction() {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    self.run();
+                    break;
+                },
+            };
+
+            // Waiting for the display interrupt or for a key takes the rest of the frame.
+            let drawing = matches!(instruction, Instruction::Draw(_));
+            if (drawing && self.quirks().vblank && self.frame_drawn) || self.waiting_for_key() {
+                self.cycles = 0;
+            } else {
+                self.cycles -= timing::vip_cycles(&instruction, self) as i64;
+            }
+
+            self.run();
+        }
+    }
+
+    pub fn state(&self) -> ExecutionState {
+        self.state
+    }
+
+    pub fn register(&self, x: Register) -> u8 {
+        self.v[x.index()]
+    }
+
+    pub fn set_register(&mut self, x: Register, value: u8) {
+        self.v[x.index()] = value;
+
+        if self.config.memory_layout == MemoryLayout::Vip {
+            self.poke(layout::REGISTERS + x.index(), value);
+        }
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    /// Random byte for `CXNN`, from the hook if there is one.
+    pub(crate) fn random_byte(&mut self) -> u8 {
+        match self.hooks.random {
+            Some(random) => random(),
+            None => self.rng.next_byte(),
+        }
+    }
+
+    /// Seed the built-in random generator, making `CXNN` reproducible. A zero seed is replaced by
+    /// a fixed one.
+    pub fn seed_random(&mut self, seed: u32) {
+        self.rng = XorShift::new(seed);
+    }
+
+    /// Call `hooks` from now on, replacing the previous ones.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    pub fn hooks(&self) -> Hooks {
+        self.hooks
+    }
+
+    /// Handle `0NNN` machine code calls with `handler`, replacing the previous one.
+    #[cfg(feature = "alloc")]
+    pub fn set_sys_handler(&mut self, handler: Box<dyn SysHandler>) {
+        self.sys_handler = Some(handler);
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn remove_sys_handler(&mut self) -> Option<Box<dyn SysHandler>> {
+        self.sys_handler.take()
+    }
+
+    /// Choose what happens to `0NNN` calls the handler doesn't know.
+    pub fn set_sys_policy(&mut self, policy: SysPolicy) {
+        self.sys_policy = policy;
+    }
+
+    /// Choose what happens to memory accesses past the end of the memory.
+    pub fn set_address_policy(&mut self, policy: AddressPolicy) {
+        self.bus.policy = policy;
+    }
+
+    /// Fault on writes to `region` of the memory, such as the fonts below 0x200.
+    #[cfg(feature = "alloc")]
+    pub fn protect(&mut self, region: Range<usize>) {
+        self.bus.protected.push(region);
+    }
+
+    /// Remove every protection set by [`protect`](Program::protect).
+    #[cfg(feature = "alloc")]
+    pub fn unprotect_all(&mut self) {
+        self.bus.protected.clear();
+    }
+
+    /// Observe the memory accesses of instructions with `hook`, replacing the previous one.
+    #[cfg(feature = "alloc")]
+    pub fn set_bus_hook(&mut self, hook: Box<dyn BusHook>) {
+        self.bus.hook = Some(hook);
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn remove_bus_hook(&mut self) -> Option<Box<dyn BusHook>> {
+        self.bus.hook.take()
+    }
+
+    /// Memory accesses of instructions, see [`Bus`](crate::bus::Bus).
+    pub fn access_counters(&self) -> AccessCounters {
+        self.bus.counters
+    }
+
+    pub fn reset_access_counters(&mut self) {
+        self.bus.counters = AccessCounters::default();
+    }
+}
+
+#[cfg(any(feature = "alloc", test))]
+impl Program<'static> {
+    /// The usual CHIP-8 machine, see [`EmulatorConfig::new`].
+    pub fn new() -> Self {
+        Program::with_config(EmulatorConfig::default()).expect("default configuration is valid")
+    }
+
+    /// Allocate a machine with the memory size of `config`.
+    #[cfg(feature = "alloc")]
+    pub fn with_config(config: EmulatorConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        Ok(Program::build(config, Memory::Owned(vec![0; config.memory_size])))
+    }
+
+    /// Tests build programs the usual way without the `alloc` feature, leaking their memory.
+    #[cfg(not(feature = "alloc"))]
+    pub fn with_config(config: EmulatorConfig) -> Result<Self, ConfigError> {
+        Program::with_memory(config, std::vec![0; config.memory_size].leak())
+    }
+}
+
+impl<'a> Program<'a> {
+    /// Build a machine running in `memory`, which must be at least as large as the memory size
+    /// of `config`. Only the configured memory size is used.
+    pub fn with_memory(config: EmulatorConfig, memory: &'a mut [u8]) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        if memory.len() < config.memory_size {
+            return Err(ConfigError::MemoryBuffer(memory.len()));
+        }
+
+        Ok(Program::build(config, Memory::Borrowed(memory)))
+    }
+
+    fn build(config: EmulatorConfig, memory: Memory<'a>) -> Self {
+        let mut program = Program {
             memory,
             v: [0; 16],
             i: 0,
             delay_timer: 0,
             sound_timer: 0,
-            program_counter: 0x200,
+            program_counter: config.start_address,
             stack_pointer: 0,
-            keypad: [false; 16],
-            screen: [[false; 64]; 32],
-            stack: [0; 16],
-            rng: rand::thread_rng()
+            keypad: Keypad::default(),
+            second_keypad: Keypad::default(),
+            screen: Display::new(config.display_width, config.display_height),
+            colors: ColorLayer::new(),
+            stack: [0; MAX_STACK_DEPTH],
+            rng: XorShift::default(),
+            hooks: Hooks::default(),
+            state: ExecutionState::Running,
+            #[cfg(feature = "alloc")]
+            sys_handler: None,
+            sys_policy: SysPolicy::default(),
+            bus: BusState::default(),
+            #[cfg(feature = "alloc")]
+            decoded: vec![None; if config.decode_cache { config.memory_size } else { 0 }],
+            #[cfg(feature = "alloc")]
+            blocks: Blocks::default(),
+            config,
+            defaults: config,
+            frame_drawn: false,
+            cycles: 0
+        };
+        program.reset_memory();
+
+        program
+    }
+}
+
+impl Program<'_> {
+    /// Clear everything a program can change but the memory.
+    pub(crate) fn reset_state(&mut self) {
+        self.v = [0; 16];
+        self.i = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.stack_pointer = 0;
+        self.stack = [0; MAX_STACK_DEPTH];
+        self.keypad.cancel_wait();
+        self.second_keypad.cancel_wait();
+        self.screen.clear();
+        self.colors = ColorLayer::new();
+        self.state = ExecutionState::Running;
+        self.frame_drawn = false;
+        self.cycles = 0;
+    }
+
+    /// Fill the memory as configured, then copy the fonts.
+    fn reset_memory(&mut self) {
+        let config = self.config;
+        config.memory_fill.apply(self.memory_mut());
+
+        let font = config.font_address as usize;
+        for (i, byte) in config.font.glyphs().iter().flatten().enumerate() {
+            self.memory[font + i] = *byte;
+        }
+
+        if let Some(large_font) = config.large_font {
+            let font = config.large_font_address as usize;
+            for (i, byte) in large_font.glyphs().iter().flatten().enumerate() {
+                self.memory[font + i] = *byte;
+            }
+        }
+    }
+
+    pub fn config(&self) -> &EmulatorConfig {
+        &self.config
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.config.quirks
+    }
+
+    pub fn platform(&self) -> Option<Platform> {
+        self.config.platform
+    }
+
+    /// Switch to `platform` and its quirks, resizing and clearing the screen if needed. Its start
+    /// address only applies to the next [`load`](Program::load).
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.config = self.config.platform(platform);
+
+        let (width, height) = platform.display_size();
+        if (self.screen.width(), self.screen.height()) != (width, height) {
+            self.screen.resize(width, height);
+        }
+    }
+
+    /// Go back to the platform, quirks, display size, start address, font and instructions per
+    /// frame the program was built with, e.g. before loading a ROM that doesn't need the
+    /// settings of the previous one.
+    pub fn restore_platform(&mut self) {
+        let defaults = self.defaults;
+
+        self.config.platform = defaults.platform;
+        self.config.start_address = defaults.start_address;
+        self.config.font = defaults.font;
+        self.config.quirks = defaults.quirks;
+        self.config.instructions_per_frame = defaults.instructions_per_frame;
+        self.config.display_width = defaults.display_width;
+        self.config.display_height = defaults.display_height;
+
+        let (width, height) = (defaults.display_width, defaults.display_height);
+        if (self.screen.width(), self.screen.height()) != (width, height) {
+            self.screen.resize(width, height);
+        }
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.config.timing = timing;
+        self.cycles = 0;
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.config.engine = engine;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.config.quirks = quirks;
+    }
+
+    /// Change the font used by `FX29`, copied to memory by the next [`load`](Program::load).
+    pub fn set_font(&mut self, font: Font) {
+        self.config.font = font;
+    }
+
+    /// Change the instructions executed by [`run_frame`](Program::run_frame), ignored if zero.
+    pub fn set_instructions_per_frame(&mut self, count: usize) {
+        if count > 0 {
+            self.config.instructions_per_frame = count;
         }
     }
 
-    pub fn load(&mut self, data: &[u8]) {
-        let iter = data.iter().chain(repeat(&0)).enumerate().take(4096 - 0x200);
+    /// The addressable memory, its length being the configured memory size.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.config.memory_size]
+    }
+
+    /// The addressable memory, which can be modified at will: every decoded instruction is
+    /// forgotten.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        #[cfg(feature = "alloc")]
+        self.decoded.iter_mut().for_each(|decoded| *decoded = None);
+        #[cfg(feature = "alloc")]
+        self.blocks.clear();
+        &mut self.memory[..self.config.memory_size]
+    }
+
+    /// Load `data` at the configured start address, see [`load_at`](Program::load_at).
+    pub fn load(&mut self, data: &[u8]) -> Result<LoadInfo, LoadError> {
+        self.load_at(self.config.start_address, data)
+    }
 
-        for (index, value) in iter {
-            self.memory[0x200 + index] = *value;
+    /// Load `data` at `address` and start executing from there.
+    ///
+    /// Everything else is reset as on a fresh machine, except the keys held down: the memory is
+    /// filled as configured and the fonts are copied again, the registers, stack, timers and
+    /// screen are cleared, and a halted or faulted program runs again.
+    ///
+    /// With [`MemoryLayout::Vip`], the ROM must end before the stack, at
+    /// [`layout::STACK`](crate::layout::STACK).
+    pub fn load_at(&mut self, address: u16, data: &[u8]) -> Result<LoadInfo, LoadError> {
+        let start = address as usize;
+        let memory_size = self.config.memory_size;
+        let end = match self.config.memory_layout {
+            MemoryLayout::Vip => layout::STACK.min(memory_size),
+            _ => memory_size,
+        };
+
+        if start >= memory_size {
+            return Err(LoadError::InvalidAddress(address));
+        }
+
+        if data.is_empty() {
+            return Err(LoadError::Empty);
+        }
+
+        let available = end.saturating_sub(start);
+        if data.len() > available {
+            return Err(LoadError::TooLarge { size: data.len(), available });
+        }
+
+        self.reset_state();
+        self.reset_memory();
+        if self.config.memory_layout == MemoryLayout::Vip {
+            layout::store(self);
         }
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.program_counter = address;
+
+        Ok(LoadInfo {
+            address,
+            size: data.len(),
+            crc32: checksum::crc32(data),
+            sha1: checksum::sha1(data)
+        })
     }
 
-    pub fn keydown(&mut self, key: usize) {
-        // TODO: Check key value
-        self.keypad[key] = true;
+    /// Read the ROM at `path` and load it at the configured start address.
+    #[cfg(feature = "std")]
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<LoadInfo, LoadError> {
+        let data = fs::read(path)?;
+
+        self.load(&data)
+    }
+
+    /// Decrement both timers, this must be called at 60 Hz. This also starts a new frame for the
+    /// `vblank` quirk.
+    pub fn decrement_timers(&mut self) {
+        self.frame_drawn = false;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+
+        if let Some(timers) = self.hooks.timers {
+            timers(self.delay_timer, self.sound_timer);
+        }
+    }
+
+    pub fn keydown(&mut self, key: Key) {
+        self.keypad.press(key);
+    }
+
+    pub fn keyup(&mut self, key: Key) {
+        self.keypad.release(key);
+    }
+
+    pub fn is_key_pressed(&self, key: Key) -> bool {
+        self.keypad.is_pressed(key)
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    /// Press a key of the second keypad of the CHIP-8X.
+    pub fn second_keydown(&mut self, key: Key) {
+        self.second_keypad.press(key);
     }
 
-    pub fn keyup(&mut self, key: usize) {
-        // TODO: Check key value
-        self.keypad[key] = false;
+    pub fn second_keyup(&mut self, key: Key) {
+        self.second_keypad.release(key);
+    }
+
+    pub fn second_keypad(&self) -> &Keypad {
+        &self.second_keypad
+    }
+
+    /// Whether the program is blocked on `FX0A`, waiting for a key to be pressed and released.
+    pub fn waiting_for_key(&self) -> bool {
+        self.keypad.is_waiting()
     }
 
     // fn run_instruction(&mut self, instruction: Instruction) {
@@ -124,3 +757,257 @@ impl Program {
     //     }
     // }
 }
+
+#[cfg(any(feature = "alloc", test))]
+impl Default for Program<'static> {
+    fn default() -> Self {
+        Program::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::MemoryFill;
+
+    fn loaded(rom: &[u8]) -> Program<'static> {
+        let mut program = Program::new();
+        program.load(rom).unwrap();
+        program
+    }
+
+    #[test]
+    fn register_conversions() {
+        assert_eq!(Register::try_from(0xAu8), Ok(Register::VA));
+        assert_eq!(Register::try_from(0x10usize), Err(InvalidRegister(0x10)));
+        assert!(Register::ALL.iter().enumerate().all(|(x, register)| register.index() == x));
+    }
+
+    #[test]
+    fn self_jump_halts() {
+        let mut program = loaded(&[0x60, 0x01, 0x12, 0x02]);
+
+        let state = program.run_until_stopped(10);
+        assert_eq!(state, ExecutionState::Halted(HaltReason::InfiniteLoop { address: 0x202 }));
+        assert_eq!(program.program_counter, 0x202);
+    }
+
+    #[test]
+    fn faults_stop_execution() {
+        let mut program = loaded(&[0x00, 0xEE]);
+        assert_eq!(program.run(), ExecutionState::Faulted(Fault::StackUnderflow { address: 0x200 }));
+
+        let mut program = loaded(&[0xFF, 0xFF]);
+        let fault = Fault::InvalidInstruction { address: 0x200, opcode: 0xFFFF };
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+    }
+
+    #[test]
+    fn key_wait() {
+        let mut program = loaded(&[0xF3, 0x0A, 0x00, 0xFD]);
+
+        assert_eq!(program.run(), ExecutionState::WaitingForKey { register: 3 });
+        program.keydown(Key::C);
+        assert_eq!(program.run(), ExecutionState::WaitingForKey { register: 3 });
+        program.keyup(Key::C);
+        assert_eq!(program.run(), ExecutionState::Running);
+        assert_eq!(program.v[3], 0xC);
+        assert_eq!(program.run(), ExecutionState::Halted(HaltReason::Exit));
+    }
+
+    #[test]
+    fn strict_loading() {
+        let config = EmulatorConfig::new().memory_fill(MemoryFill::Byte(0xAA));
+        let mut program = config.build().unwrap();
+
+        assert!(matches!(program.load(&[]), Err(LoadError::Empty)));
+        assert!(matches!(
+            program.load(&[0; 0xE01]),
+            Err(LoadError::TooLarge { size: 0xE01, available: 0xE00 })
+        ));
+        assert!(matches!(program.load_at(0x1000, &[0]), Err(LoadError::InvalidAddress(0x1000))));
+
+        let info = program.load_at(0x600, &[0x12, 0x34]).unwrap();
+        assert_eq!((info.address, info.size, info.crc32), (0x600, 2, 0x1899_9699));
+        assert_eq!(program.program_counter, 0x600);
+        assert_eq!(program.memory()[0x5FF..0x603], [0xAA, 0x12, 0x34, 0xAA]);
+        assert_eq!(program.memory()[..5], SPRITES[0]);
+
+        // Loading again leaves nothing of the previous ROM.
+        program.load(&[0x00, 0xE0]).unwrap();
+        assert_eq!(program.memory()[0x600], 0xAA);
+    }
+
+    #[test]
+    fn reload_after_halt() {
+        // v0 = 5, I = 0x300, delay timer = v0, draw, call 0x20C, then exit.
+        let mut program = loaded(&[
+            0x60, 0x05, 0xA3, 0x00, 0xF0, 0x15, 0xD0, 0x05, 0x22, 0x0C, 0x00, 0x00, 0x00, 0xFD,
+        ]);
+        program.run_until_stopped(10);
+        assert_eq!(program.state(), ExecutionState::Halted(HaltReason::Exit));
+
+        program.load(&[0xF1, 0x0A]).unwrap();
+        assert_eq!(program.state(), ExecutionState::Running);
+        assert_eq!((program.register(Register::V0), program.i(), program.delay_timer), (0, 0, 0));
+        assert_eq!((program.stack_pointer, program.stack[0]), (0, 0));
+        assert!(program.screen.rows().flatten().all(|pixel| !pixel));
+
+        // A new ROM doesn't inherit the wait for a key either.
+        assert_eq!(program.run(), ExecutionState::WaitingForKey { register: 1 });
+        program.load(&[0x61, 0x07]).unwrap();
+        assert!(!program.waiting_for_key());
+        assert_eq!(program.run(), ExecutionState::Running);
+        assert_eq!(program.register(Register::V1), 7);
+    }
+
+    #[test]
+    fn memory_sized_by_the_caller() {
+        let config = EmulatorConfig::new().memory_size(0x800);
+
+        let mut memory = [0xFF; 0x7FF];
+        assert!(matches!(
+            Program::with_memory(config, &mut memory),
+            Err(ConfigError::MemoryBuffer(0x7FF))
+        ));
+
+        let mut memory = [0xFF; 0x900];
+        let mut program = config.build_in(&mut memory).unwrap();
+        program.load(&[0x00, 0xE0]).unwrap();
+        assert_eq!(program.memory().len(), 0x800);
+        assert_eq!(program.memory()[..5], SPRITES[0]);
+
+        // The memory isn't part of the program anymore.
+        assert!(core::mem::size_of::<Program>() < 0x800);
+    }
+
+    #[test]
+    fn platform_start_address() {
+        let mut program = EmulatorConfig::new().platform(Platform::Chip8X).build().unwrap();
+        assert_eq!(program.load(&[0x00, 0xE0]).unwrap().address, 0x300);
+
+        program.set_platform(Platform::OriginalChip8);
+        assert_eq!(program.load(&[0x00, 0xE0]).unwrap().address, 0x200);
+
+        program.restore_platform();
+        assert_eq!(program.config().get_start_address(), 0x300);
+    }
+
+    #[test]
+    fn quirks() {
+        // v0 = 0xFF, v1 = 0x81, v2 = 0x83, v2 <<= 1, v1 |= v2, store v0 and v1 at I, then draw
+        // the byte at I twice at (63, 31).
+        let rom = [0x60, 0xFF, 0x61, 0x81, 0x62, 0x83, 0x82, 0x1E, 0x81, 0x21, 0xF1, 0x55,
+            0x6A, 0x3F, 0x6B, 0x1F, 0xDA, 0xB1, 0xDA, 0xB1];
+
+        let mut program = EmulatorConfig::new().build().unwrap();
+        program.load(&rom).unwrap();
+        program.run_until_stopped(5);
+        assert_eq!((program.v[1], program.v[2], program.v[0xF]), (0x87, 0x06, 1));
+        program.run_until_stopped(4);
+        assert_eq!(program.i(), 0);
+        assert!(program.screen.get(63, 31) && program.screen.get(0, 31));
+
+        let mut program = EmulatorConfig::new().platform(Platform::OriginalChip8).build().unwrap();
+        program.load(&rom).unwrap();
+        program.run_until_stopped(5);
+        assert_eq!((program.v[1], program.v[2], program.v[0xF]), (0x83, 0x02, 0));
+        program.run_until_stopped(4);
+        assert_eq!(program.i(), 2);
+        assert!(program.screen.get(63, 31) && !program.screen.get(0, 31));
+
+        // The second sprite waits for the next frame.
+        program.run();
+        assert_eq!(program.program_counter, 0x212);
+        program.decrement_timers();
+        program.run();
+        assert_eq!(program.program_counter, 0x214);
+    }
+
+    #[test]
+    fn hires() {
+        let mut rom = vec![0; 0xC4];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        // Clear the screen, then draw the first row of 0 at (0, 60).
+        rom[0xC0..].copy_from_slice(&[0x02, 0x30, 0xD0, 0x11]);
+
+        let mut program = EmulatorConfig::new().build().unwrap();
+        program.set_platform(Platform::HiresChip8);
+        program.load(&rom).unwrap();
+        program.set_register(Register::V1, 60);
+        program.screen.set(5, 5, true);
+
+        program.run_until_stopped(3);
+        assert_eq!(program.state(), ExecutionState::Running);
+        assert_eq!(program.program_counter, 0x2C4);
+        assert!(program.screen.get(0, 60) && !program.screen.get(5, 5));
+    }
+
+    #[test]
+    fn font_locations() {
+        let config = EmulatorConfig::new().font_address(0x100).large_font_address(0x50);
+        let mut program = config.build().unwrap();
+        program.load(&[0x60, 0x1A, 0xF0, 0x29, 0xF0, 0x30]).unwrap();
+
+        program.run_until_stopped(2);
+        assert_eq!(program.i(), 0x100 + 0xA * 5);
+        program.run();
+        assert_eq!(program.i(), 0x50 + 0xA * 10);
+
+        let mut program = EmulatorConfig::vip().build().unwrap();
+        program.load(&[0xF0, 0x30]).unwrap();
+        let fault = Fault::InvalidInstruction { address: 0x200, opcode: 0xF030 };
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn self_modifying_code() {
+        // 0x20C is called, then overwritten with v1 += 5 by FX55 and executed again.
+        let rom = [
+            0xA2, 0x0C, 0x60, 0x71, 0x61, 0x05, 0x22, 0x0C,
+            0xF1, 0x55, 0x12, 0x0C, 0x63, 0x01, 0x00, 0xEE,
+        ];
+
+        for enabled in [true, false] {
+            let mut program = EmulatorConfig::new().decode_cache(enabled).build().unwrap();
+            program.load(&rom).unwrap();
+
+            let state = program.run_until_stopped(20);
+            assert_eq!(state, ExecutionState::Faulted(Fault::StackUnderflow { address: 0x20E }));
+            assert_eq!((program.register(Register::V1), program.register(Register::V3)), (10, 1));
+        }
+    }
+
+    #[test]
+    fn program_counter_wraps() {
+        let mut program = EmulatorConfig::new().memory_size(0x10000).build().unwrap();
+
+        // v0 = 1 at the end of the memory, then skip from 0xFFFE to 2.
+        program.load_at(0xFFFC, &[0x60, 0x01, 0x30, 0x01]).unwrap();
+        program.run_until_stopped(2);
+        assert_eq!((program.state(), program.program_counter), (ExecutionState::Running, 2));
+
+        // The return address of a call at 0xFFFE is 0.
+        program.load_at(0xFFFE, &[0x23, 0x00]).unwrap();
+        program.run();
+        assert_eq!(program.stack[0], 0);
+    }
+
+    #[test]
+    fn configured_machine() {
+        let config = EmulatorConfig::vip().start_address(0x300).memory_size(0x400);
+        let mut program = config.build().unwrap();
+        program.load(&[0x23, 0x00]).unwrap();
+
+        assert_eq!(program.program_counter, 0x300);
+        assert_eq!(program.memory().len(), 0x400);
+
+        // 0x300 calls itself until the 12 entries of the stack are used.
+        let state = program.run_frame();
+        assert_eq!(state, ExecutionState::Running);
+        let state = program.run_until_stopped(10);
+        assert_eq!(state, ExecutionState::Faulted(Fault::StackOverflow { address: 0x300 }));
+    }
+}
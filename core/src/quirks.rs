@@ -0,0 +1,62 @@
+//! Behaviours that differ between CHIP-8 interpreters.
+//!
+//! The names follow the quirks of the community CHIP-8 database.
+
+/// Set of quirks a program runs with.
+///
+/// The default reproduces what this interpreter has always done: shifts in place, `FX55` and
+/// `FX65` leaving `I` unchanged and sprites wrapping around the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift VX in place instead of shifting VY into VX.
+    pub shift: bool,
+    /// `FX55` and `FX65` increment I by X instead of X + 1.
+    pub memory_increment_by_x: bool,
+    /// `FX55` and `FX65` leave I unchanged, taking precedence over `memory_increment_by_x`.
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites drawn past the edges of the screen wrap around instead of being clipped.
+    pub wrap: bool,
+    /// `BNNN` jumps to NNN plus VX, X being the highest nibble of NNN, instead of V0.
+    pub jump: bool,
+    /// `DXYN` waits for the next frame if something was already drawn in the current one.
+    pub vblank: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF.
+    pub logic: bool,
+}
+
+impl Quirks {
+    /// Every quirk disabled, as modern CHIP-8 interpreters do.
+    pub const NONE: Quirks = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: false,
+        jump: false,
+        vblank: false,
+        logic: false,
+    };
+
+    /// Update the quirk named `name` as in the community database, returning whether it is
+    /// known.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "memoryIncrementByX" => &mut self.memory_increment_by_x,
+            "memoryLeaveIUnchanged" => &mut self.memory_leave_i_unchanged,
+            "wrap" => &mut self.wrap,
+            "jump" => &mut self.jump,
+            "vblank" => &mut self.vblank,
+            "logic" => &mut self.logic,
+            _ => return false,
+        };
+
+        *quirk = value;
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks { shift: true, memory_leave_i_unchanged: true, wrap: true, ..Quirks::NONE }
+    }
+}
@@ -0,0 +1,377 @@
+//! Execution of basic blocks compiled to closures.
+//!
+//! With [`Engine::Recompiler`], straight runs of instructions are decoded once into a chain of
+//! closures, cached by the address they start at, and executed without fetching or decoding
+//! anything. Blocks end after any instruction that can branch or wait, so executing one only
+//! stops early when an instruction faults, or when a write to memory invalidates blocks: CHIP-8
+//! programs commonly modify themselves with `FX55` and `FX33`.
+//!
+//! Programs behave exactly as with the interpreter, instructions being executed one by one with
+//! the program counter kept up to date. The recompiler is only used by
+//! [`Program::run_until_stopped`], and so by [`Program::run_frame`] with
+//! [`Timing::Instructions`](crate::timing::Timing::Instructions), and not with the VIP memory
+//! layout, whose memory is synchronised after every instruction.
+
+use crate::instructions::Instruction;
+use crate::layout::MemoryLayout;
+use crate::program::{ Cursor, Program };
+use crate::state::ExecutionState;
+
+use alloc::{ boxed::Box, rc::Rc, vec::Vec };
+
+/// How instructions are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Fetch and decode every instruction before executing it.
+    #[default]
+    Interpreter,
+    /// Execute basic blocks compiled to closures.
+    Recompiler,
+}
+
+/// Longest block compiled, in instructions.
+const MAX_BLOCK_LENGTH: usize = 64;
+
+type Op = Box<dyn Fn(&mut Program) -> Cursor>;
+
+struct Block {
+    start: usize,
+    /// Address past the last instruction.
+    end: usize,
+    ops: Vec<Op>,
+}
+
+/// Compiled blocks of a program.
+#[derive(Default)]
+pub(crate) struct Blocks {
+    /// Block starting at each address, empty until the first block is compiled.
+    entries: Vec<Option<Rc<Block>>>,
+    /// Number of blocks containing each byte.
+    covered: Vec<u16>,
+    /// Incremented whenever blocks are invalidated, so that the block being executed knows it
+    /// may have been modified.
+    generation: u64,
+}
+
+impl Blocks {
+    /// Drop the blocks containing the byte at `address`.
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        match self.covered.get(address) {
+            Some(count) if *count > 0 => {},
+            _ => return,
+        }
+
+        for index in 0..self.entries.len() {
+            let overlaps = match &self.entries[index] {
+                Some(block) => (block.start..block.end).contains(&address),
+                None => false,
+            };
+
+            if overlaps {
+                self.remove(index);
+            }
+        }
+
+        self.generation += 1;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.covered.clear();
+        self.generation += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        if let Some(block) = self.entries[index].take() {
+            for count in &mut self.covered[block.start..block.end] {
+                *count -= 1;
+            }
+        }
+    }
+
+    fn insert(&mut self, block: Block, memory_size: usize) -> Rc<Block> {
+        if self.entries.is_empty() {
+            self.entries.resize(memory_size, None);
+            self.covered.resize(memory_size, 0);
+        }
+
+        for count in &mut self.covered[block.start..block.end] {
+            *count += 1;
+        }
+
+        let start = block.start;
+        let block = Rc::new(block);
+        self.entries[start] = Some(block.clone());
+
+        block
+    }
+}
+
+/// Whether execution may not continue with the next instruction after `instruction`, faults
+/// aside.
+fn ends_block(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Clear(_)
+            | Instruction::SetRegister(_)
+            | Instruction::AddRegister(_)
+            | Instruction::SetVxToVy(_)
+            | Instruction::SetVxToVxOrVy(_)
+            | Instruction::SetVxToVxAndVy(_)
+            | Instruction::SetVxToVxXorVy(_)
+            | Instruction::SetVxToVxAndVyCarry(_)
+            | Instruction::SetVxToVxSubVy(_)
+            | Instruction::SetVxToVxShr(_)
+            | Instruction::SetVxToVySubVx(_)
+            | Instruction::SetVxToVxShl(_)
+            | Instruction::SetVxToVxAddVyPacked(_)
+            | Instruction::SetIToAddress(_)
+            | Instruction::SetVxToRandomAndValue(_)
+            | Instruction::SetVxToDelayTimer(_)
+            | Instruction::SetDelayTimerToVx(_)
+            | Instruction::SetSoundTimerToVx(_)
+            | Instruction::AddVxToI(_)
+            | Instruction::SetIToSpriteLocation(_)
+            | Instruction::SetIToLargeSpriteLocation(_)
+            | Instruction::StoreBCD(_)
+            | Instruction::StoreRegisters(_)
+            | Instruction::ReadRegisters(_)
+    )
+}
+
+/// Closure executing `instruction`, specialised for the most common ones.
+fn compile(instruction: Instruction) -> Op {
+    match instruction {
+        Instruction::SetRegister(i) => Box::new(move |program| {
+            program.v[i.x] = i.value;
+            Cursor::Next
+        }),
+        Instruction::AddRegister(i) => Box::new(move |program| {
+            program.v[i.x] = program.v[i.x].wrapping_add(i.value);
+            Cursor::Next
+        }),
+        Instruction::SetVxToVy(i) => Box::new(move |program| {
+            program.v[i.x] = program.v[i.y];
+            Cursor::Next
+        }),
+        Instruction::SetIToAddress(i) => Box::new(move |program| {
+            program.i = i.address;
+            Cursor::Next
+        }),
+        Instruction::AddVxToI(i) => Box::new(move |program| {
+            program.i = program.i.wrapping_add(program.v[i.x] as u16);
+            Cursor::Next
+        }),
+        instruction => Box::new(move |program| instruction.run(program)),
+    }
+}
+
+/// Compile the block starting at `start`, or `None` if no instruction fits there.
+fn compile_block(program: &Program, start: usize) -> Option<Block> {
+    let memory = program.memory();
+    let mut ops = Vec::new();
+    let mut end = start;
+
+    while ops.len() < MAX_BLOCK_LENGTH && end + 1 < memory.len() {
+        let instruction = Instruction::from((memory[end] as u16) << 8 | memory[end + 1] as u16);
+        ops.push(compile(instruction));
+        end += 2;
+
+        if ends_block(&instruction) {
+            break;
+        }
+    }
+
+    if ops.is_empty() {
+        None
+    } else {
+        Some(Block { start, end, ops })
+    }
+}
+
+/// Whether `program` executes instructions with the recompiler.
+pub(crate) fn enabled(program: &Program) -> bool {
+    program.config.engine == Engine::Recompiler
+        && program.config.memory_layout == MemoryLayout::Separate
+}
+
+/// Execute at most `limit` instructions, stopping early if the program halts or faults.
+pub(crate) fn run(program: &mut Program, limit: usize) -> ExecutionState {
+    let mut executed = 0;
+
+    while executed < limit && !program.state.is_stopped() {
+        let start = program.program_counter as usize;
+        let cached = program.blocks.entries.get(start).and_then(Option::clone);
+        let block = match cached {
+            Some(block) => block,
+            None => match compile_block(program, start) {
+                Some(block) => program.blocks.insert(block, program.config.memory_size),
+                None => {
+                    // Let the interpreter report the fault.
+                    program.run();
+                    executed += 1;
+                    continue;
+                },
+            },
+        };
+
+        let generation = program.blocks.generation;
+        for op in block.ops.iter().take(limit - executed) {
+            executed += 1;
+
+            let cursor = op(program);
+            let next = matches!(cursor, Cursor::Next);
+            program.advance(cursor);
+
+            if !next || program.blocks.generation != generation {
+                break;
+            }
+        }
+    }
+
+    program.state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmulatorConfig;
+    use crate::keypad::Key;
+    use crate::program::Register;
+    use crate::sys::SysPolicy;
+
+    fn programs(rom: &[u8]) -> [Program<'static>; 2] {
+        let build = |engine| {
+            let mut program = EmulatorConfig::new().engine(engine).build().unwrap();
+            program.set_sys_policy(SysPolicy::Ignore);
+            program.load(rom).unwrap();
+            program
+        };
+
+        [build(Engine::Interpreter), build(Engine::Recompiler)]
+    }
+
+    fn assert_same(interpreted: &Program, recompiled: &Program, seed: u32) {
+        assert_eq!(interpreted.state, recompiled.state, "seed {}", seed);
+        assert_eq!(interpreted.program_counter, recompiled.program_counter, "seed {}", seed);
+        assert_eq!(interpreted.v, recompiled.v, "seed {}", seed);
+        assert_eq!(interpreted.i, recompiled.i, "seed {}", seed);
+        assert_eq!(interpreted.stack_pointer, recompiled.stack_pointer, "seed {}", seed);
+        assert_eq!(interpreted.stack, recompiled.stack, "seed {}", seed);
+        assert_eq!(interpreted.delay_timer, recompiled.delay_timer, "seed {}", seed);
+        assert_eq!(interpreted.screen, recompiled.screen, "seed {}", seed);
+        assert!(interpreted.memory() == recompiled.memory(), "seed {}", seed);
+    }
+
+    /// Random valid instructions, except `CXNN` whose random numbers can't be compared, `00FD`
+    /// which would stop the program, and the CHIP-8X instructions. Invalid opcodes are drawn
+    /// again.
+    ///
+    /// The first 192 bytes jump around, ending with a jump back to the start, and call
+    /// subroutines in the last 64 bytes, where a return comes every few instructions.
+    fn random_rom(seed: u32) -> Vec<u8> {
+        const SUBROUTINES: usize = 0xC0;
+
+        let mut state = seed;
+        let mut rom = Vec::new();
+
+        while rom.len() < 0x100 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let mut opcode = (state >> 8) as u16;
+            let subroutine = rom.len() >= SUBROUTINES;
+
+            if subroutine && (rom.len() % 8 == 6 || rom.len() >= 0xFC) {
+                opcode = 0x00EE;
+            } else if rom.len() >= SUBROUTINES - 4 && !subroutine {
+                opcode = 0x1200;
+            }
+
+            // Keep jumps and calls on instruction boundaries, in their part of the ROM, without
+            // jumping in place.
+            match opcode >> 12 {
+                0x1 if !subroutine => {
+                    let target = (opcode & 0xFE) as usize % SUBROUTINES;
+                    if target == rom.len() {
+                        continue;
+                    }
+
+                    opcode = 0x1200 | target as u16;
+                },
+                0x2 if !subroutine => opcode = 0x2200 | SUBROUTINES as u16 | (opcode & 0x3E),
+                0x1 | 0x2 | 0xB => continue,
+                // Writes through I mustn't turn the ROM into invalid instructions.
+                0xA => opcode = 0xA300 | (opcode & 0xFF),
+                _ => {},
+            }
+
+            let skipped = matches!(
+                Instruction::from(opcode),
+                Instruction::InvalidInstruction(_) | Instruction::Exit(_)
+            );
+            let chip8x = match opcode >> 12 {
+                0x5 => opcode & 0xF != 0,
+                0xE => matches!(opcode & 0xFF, 0xF2 | 0xF5),
+                _ => false,
+            };
+
+            if skipped || chip8x || opcode >> 12 == 0xC {
+                continue;
+            }
+
+            rom.extend_from_slice(&opcode.to_be_bytes());
+        }
+
+        rom
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        for seed in 0..200 {
+            let [mut interpreted, mut recompiled] = programs(&random_rom(seed));
+            let mut frames = 0;
+
+            for frame in 0..30 {
+                if frame == 10 {
+                    interpreted.keydown(Key::A);
+                    recompiled.keydown(Key::A);
+                }
+
+                if frame == 12 {
+                    interpreted.keyup(Key::A);
+                    recompiled.keyup(Key::A);
+                }
+
+                interpreted.run_frame();
+                recompiled.run_frame();
+                assert_same(&interpreted, &recompiled, seed);
+
+                if !recompiled.state.is_stopped() {
+                    frames += 1;
+                }
+            }
+
+            // Programs stopping early would leave most instructions untested.
+            assert!(frames >= 20, "seed {} only ran {} frames", seed, frames);
+        }
+    }
+
+    #[test]
+    fn self_modifying_blocks() {
+        // v0 and v1 are stored over the instruction following FX55, in the same block, which
+        // becomes v1 += v1: the loop keeps rewriting it with the new value of v1.
+        let rom = [
+            0x60, 0x71, 0x61, 0x05, 0xA2, 0x0A, 0x72, 0x01,
+            0xF1, 0x55, 0x63, 0x01, 0x12, 0x04,
+        ];
+        let [mut interpreted, mut recompiled] = programs(&rom);
+
+        for _ in 0..3 {
+            interpreted.run_until_stopped(7);
+            recompiled.run_until_stopped(7);
+            assert_same(&interpreted, &recompiled, 0);
+        }
+
+        assert_eq!(recompiled.register(Register::V1), 80);
+        assert_eq!(recompiled.register(Register::V3), 0);
+    }
+}
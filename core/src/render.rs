@@ -0,0 +1,372 @@
+//! Rasterization of the screen into RGBA8 buffers.
+//!
+//! The buffers produced here are laid out exactly as expected by the canvas `ImageData`
+//! constructor, so frontends don't need to reimplement the conversion themselves.
+
+#[cfg(feature = "alloc")]
+use alloc::{ vec, vec::Vec };
+use core::num::NonZeroUsize;
+
+/// Anything that can be rasterized by a [`Renderer`].
+///
+/// Each pixel is reported as a palette index between 0 and 3. Monochrome screens only ever use
+/// the indices 0 (background) and 1 (foreground), the two others are used by multi-plane modes.
+pub trait Frame {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn pixel(&self, x: usize, y: usize) -> u8;
+}
+
+impl<const W: usize, const H: usize> Frame for [[bool; W]; H] {
+    fn width(&self) -> usize {
+        W
+    }
+
+    fn height(&self) -> usize {
+        H
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        self[y][x] as u8
+    }
+}
+
+/// Two monochrome planes combined into a four colour frame.
+///
+/// A pixel lit on the first plane only uses the palette index 1, on the second plane only the
+/// index 2 and on both planes the index 3.
+pub struct Planes<'a, F: Frame + ?Sized> {
+    pub first: &'a F,
+    pub second: &'a F,
+}
+
+impl<'a, F: Frame + ?Sized> Frame for Planes<'a, F> {
+    fn width(&self) -> usize {
+        self.first.width()
+    }
+
+    fn height(&self) -> usize {
+        self.first.height()
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        (self.first.pixel(x, y) & 1) | ((self.second.pixel(x, y) & 1) << 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0x00, 0x00, 0x00);
+    pub const WHITE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b, a: 0xFF }
+    }
+
+    /// Build an opaque color from a `0xRRGGBB` value.
+    pub const fn from_hex(hex: u32) -> Self {
+        Color::rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+    }
+
+    pub const fn to_rgba(self) -> [u8; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+/// Colors used for each palette index of a [`Frame`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    /// White pixels on a black background.
+    pub const DEFAULT: Palette = Palette::monochrome(Color::BLACK, Color::WHITE);
+
+    /// The yellow-on-brown palette of the Octo IDE, with its two extra colors for the second
+    /// plane and the overlap of both planes.
+    pub const OCTO: Palette = Palette::new([
+        Color::from_hex(0x996600),
+        Color::from_hex(0xFFCC00),
+        Color::from_hex(0xFF6600),
+        Color::from_hex(0x662200),
+    ]);
+
+    pub const fn new(colors: [Color; 4]) -> Self {
+        Palette { colors }
+    }
+
+    /// Palette for single plane screens, every lit pixel uses `foreground`.
+    pub const fn monochrome(background: Color, foreground: Color) -> Self {
+        Palette::new([background, foreground, foreground, foreground])
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    pub fn color(&self, index: u8) -> Color {
+        self.colors[(index & 0x3) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::DEFAULT
+    }
+}
+
+/// Converts frames into RGBA8 buffers, each pixel being upscaled to a `scale` by `scale` square.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Renderer {
+    scale: NonZeroUsize,
+    palette: Palette,
+}
+
+impl Renderer {
+    pub fn new(scale: NonZeroUsize, palette: Palette) -> Self {
+        Renderer { scale, palette }
+    }
+
+    pub fn scale(&self) -> NonZeroUsize {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: NonZeroUsize) {
+        self.scale = scale;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Width and height, in pixels, of the image produced for `frame`.
+    pub fn dimensions<F: Frame + ?Sized>(&self, frame: &F) -> (usize, usize) {
+        let scale = self.scale.get();
+        (frame.width() * scale, frame.height() * scale)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn render<F: Frame + ?Sized>(&self, frame: &F) -> Vec<u8> {
+        let (width, height) = self.dimensions(frame);
+        let mut buffer = vec![0; width * height * 4];
+
+        self.render_into(frame, &mut buffer);
+
+        buffer
+    }
+
+    /// Render `frame` into an existing buffer, which must be exactly `width * height * 4` bytes
+    /// long, to avoid allocating a new one on every frame.
+    pub fn render_into<F: Frame + ?Sized>(&self, frame: &F, buffer: &mut [u8]) {
+        let (width, height) = self.dimensions(frame);
+        assert_eq!(buffer.len(), width * height * 4, "buffer size doesn't match the frame");
+
+        let palette = &self.palette;
+
+        self.rasterize(frame.width(), buffer, |x, y| palette.color(frame.pixel(x, y)).to_rgba());
+    }
+
+    /// Render an intensity buffer, such as the output of a
+    /// [`PhosphorFilter`](crate::filter::PhosphorFilter), blending the background and foreground
+    /// colors of the palette.
+    #[cfg(feature = "alloc")]
+    pub fn render_intensities(&self, intensities: &Intensities) -> Vec<u8> {
+        let (width, height) = intensities.dimensions();
+        let scale = self.scale.get();
+        let mut buffer = vec![0; width * height * scale * scale * 4];
+
+        self.render_intensities_into(intensities, &mut buffer);
+
+        buffer
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn render_intensities_into(&self, intensities: &Intensities, buffer: &mut [u8]) {
+        let (width, height) = intensities.dimensions();
+        let scale = self.scale.get();
+        let expected = width * height * scale * scale * 4;
+        assert_eq!(buffer.len(), expected, "buffer size doesn't match the intensities");
+
+        let background = self.palette.background();
+        let foreground = self.palette.foreground();
+
+        self.rasterize(intensities.width(), buffer, |x, y| {
+            blend(background, foreground, intensities.value(x, y)).to_rgba()
+        });
+    }
+
+    /// Upscale an RGBA8 image `width` pixels wide, for displays whose colors don't come from a
+    /// palette, such as the CHIP-8X colour board or MegaChip.
+    #[cfg(feature = "alloc")]
+    pub fn render_rgba(&self, width: usize, pixels: &[u8]) -> Vec<u8> {
+        let scale = self.scale.get();
+        let mut buffer = vec![0; pixels.len() * scale * scale];
+
+        self.rasterize(width, &mut buffer, |x, y| {
+            let at = (y * width + x) * 4;
+            [pixels[at], pixels[at + 1], pixels[at + 2], pixels[at + 3]]
+        });
+
+        buffer
+    }
+
+    fn rasterize<C>(&self, width: usize, buffer: &mut [u8], color: C)
+    where
+        C: Fn(usize, usize) -> [u8; 4]
+    {
+        let scale = self.scale.get();
+        let stride = width * scale * 4;
+
+        for (row, line) in buffer.chunks_exact_mut(stride * scale).enumerate() {
+            let (first, rest) = line.split_at_mut(stride);
+
+            for (column, pixel) in first.chunks_exact_mut(4 * scale).enumerate() {
+                let color = color(column, row);
+
+                for chunk in pixel.chunks_exact_mut(4) {
+                    chunk.copy_from_slice(&color);
+                }
+            }
+
+            for copy in rest.chunks_exact_mut(stride) {
+                copy.copy_from_slice(first);
+            }
+        }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer::new(NonZeroUsize::MIN, Palette::DEFAULT)
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn blend(from: Color, to: Color, amount: u8) -> Color {
+    let mix = |a: u8, b: u8| {
+        let amount = amount as u16;
+        ((a as u16 * (0xFF - amount) + b as u16 * amount) / 0xFF) as u8
+    };
+
+    Color {
+        r: mix(from.r, to.r),
+        g: mix(from.g, to.g),
+        b: mix(from.b, to.b),
+        a: mix(from.a, to.a),
+    }
+}
+
+/// Per-pixel intensities, from 0 (background) to 255 (foreground).
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Intensities {
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl Intensities {
+    pub fn new(width: usize, height: usize) -> Self {
+        Intensities { width, height, values: vec![0; width * height] }
+    }
+
+    pub(crate) fn from_values(width: usize, height: usize, values: Vec<u8>) -> Self {
+        assert_eq!(values.len(), width * height);
+
+        Intensities { width, height, values }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn value(&self, x: usize, y: usize) -> u8 {
+        self.values[y * self.width + x]
+    }
+
+    pub fn values(&self) -> &[u8] {
+        &self.values
+    }
+
+    pub(crate) fn values_mut(&mut self) -> &mut [u8] {
+        &mut self.values
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    fn scale(factor: usize) -> NonZeroUsize {
+        NonZeroUsize::new(factor).unwrap()
+    }
+
+    #[test]
+    fn render_scales_pixels() {
+        let mut screen = [[false; 2]; 2];
+        screen[0][1] = true;
+
+        let renderer = Renderer::new(scale(2), Palette::DEFAULT);
+        let buffer = renderer.render(&screen);
+
+        assert_eq!(renderer.dimensions(&screen), (4, 4));
+        assert_eq!(buffer.len(), 4 * 4 * 4);
+
+        let pixel = |x: usize, y: usize| &buffer[(y * 4 + x) * 4..(y * 4 + x + 1) * 4];
+        assert_eq!(pixel(0, 0), &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(2, 0), &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(3, 1), &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(3, 2), &[0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn render_rgba_scales_pixels() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        let buffer = Renderer::new(scale(2), Palette::DEFAULT).render_rgba(2, &pixels);
+
+        assert_eq!(buffer.len(), 4 * 2 * 4);
+        assert_eq!(buffer[..16], [1, 2, 3, 4, 1, 2, 3, 4, 5, 6, 7, 8, 5, 6, 7, 8]);
+        assert_eq!(buffer[16..], buffer[..16]);
+    }
+
+    #[test]
+    fn planes_use_four_colors() {
+        let first = [[true, true, false, false]];
+        let second = [[true, false, true, false]];
+        let planes = Planes { first: &first, second: &second };
+
+        let renderer = Renderer::new(NonZeroUsize::MIN, Palette::OCTO);
+        let buffer = renderer.render(&planes);
+        let colors: Vec<_> = buffer.chunks(4).collect();
+
+        assert_eq!(colors[0], &Palette::OCTO.colors[3].to_rgba());
+        assert_eq!(colors[1], &Palette::OCTO.colors[1].to_rgba());
+        assert_eq!(colors[2], &Palette::OCTO.colors[2].to_rgba());
+        assert_eq!(colors[3], &Palette::OCTO.colors[0].to_rgba());
+    }
+}
@@ -0,0 +1,107 @@
+//! Loading of ROMs in the memory of a [`Program`](crate::program::Program).
+
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Content of the memory not covered by the fonts or the ROM, applied every time a ROM is
+/// loaded so runs don't depend on what was there before.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryFill {
+    #[default]
+    Zero,
+    Byte(u8),
+    /// Repeat the pattern from address 0, an empty pattern meaning zeroes.
+    Pattern(&'static [u8]),
+}
+
+impl MemoryFill {
+    pub(crate) fn apply(self, memory: &mut [u8]) {
+        match self {
+            MemoryFill::Zero => memory.fill(0),
+            MemoryFill::Byte(value) => memory.fill(value),
+            MemoryFill::Pattern([]) => memory.fill(0),
+            MemoryFill::Pattern(pattern) => {
+                for (byte, value) in memory.iter_mut().zip(pattern.iter().cycle()) {
+                    *byte = *value;
+                }
+            },
+        }
+    }
+}
+
+/// Description of a loaded ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadInfo {
+    /// Address of the first byte, where execution starts.
+    pub address: u16,
+    pub size: usize,
+    /// CRC-32 of the ROM, see [`crc32`](crate::checksum::crc32).
+    pub crc32: u32,
+    /// SHA-1 of the ROM, identifying it in the community database.
+    pub sha1: [u8; 20],
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Empty,
+    /// The ROM doesn't fit between its load address and the end of the memory.
+    TooLarge { size: usize, available: usize },
+    /// The load address is outside of the memory.
+    InvalidAddress(u16),
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "the ROM is empty"),
+            LoadError::TooLarge { size, available } => {
+                write!(f, "the ROM is {} bytes but only {} bytes are available", size, available)
+            },
+            LoadError::InvalidAddress(address) => {
+                write!(f, "load address {:#x} is outside of the memory", address)
+            },
+            #[cfg(feature = "std")]
+            LoadError::Io(error) => write!(f, "couldn't read the ROM: {}", error),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills() {
+        let mut memory = [1u8; 5];
+
+        MemoryFill::Pattern(&[0xA, 0xB]).apply(&mut memory);
+        assert_eq!(memory, [0xA, 0xB, 0xA, 0xB, 0xA]);
+
+        MemoryFill::Byte(0xFF).apply(&mut memory);
+        assert_eq!(memory, [0xFF; 5]);
+
+        MemoryFill::Pattern(&[]).apply(&mut memory);
+        assert_eq!(memory, [0; 5]);
+    }
+}
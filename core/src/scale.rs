@@ -0,0 +1,228 @@
+//! Edge-aware pixel-art upscaling.
+//!
+//! Upscalers work on palette indices (or intensities) rather than on colors, their output can
+//! then be handed to a [`Renderer`](crate::render::Renderer) like any other frame, possibly with
+//! an extra nearest-neighbour scale factor.
+
+use crate::render::{ Frame, Intensities };
+
+use alloc::{ vec, vec::Vec };
+use core::num::NonZeroUsize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaler {
+    /// Plain nearest-neighbour scaling by the given factor.
+    Nearest(NonZeroUsize),
+    /// Eric's Pixel Expansion, doubling the size of the image.
+    Epx,
+    /// The Scale2x (also known as AdvMAME2x) algorithm. Its output is identical to EPX, but it
+    /// is expressed with fewer comparisons.
+    Scale2x,
+    /// The Scale3x (also known as AdvMAME3x) algorithm.
+    Scale3x,
+}
+
+impl Default for Scaler {
+    fn default() -> Self {
+        Scaler::Nearest(NonZeroUsize::MIN)
+    }
+}
+
+/// Frame produced by a [`Scaler`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScaledFrame {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Frame for ScaledFrame {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Scaler {
+    /// How many output pixels are produced, horizontally and vertically, for each input pixel.
+    pub fn factor(&self) -> usize {
+        match self {
+            Scaler::Nearest(factor) => factor.get(),
+            Scaler::Epx | Scaler::Scale2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    pub fn scale<F: Frame + ?Sized>(&self, frame: &F) -> ScaledFrame {
+        let (width, height) = (frame.width(), frame.height());
+        let pixels = self.scale_grid(width, height, |x, y| frame.pixel(x, y));
+
+        ScaledFrame { width: width * self.factor(), height: height * self.factor(), pixels }
+    }
+
+    /// Upscale the output of a [`PhosphorFilter`](crate::filter::PhosphorFilter).
+    pub fn scale_intensities(&self, intensities: &Intensities) -> Intensities {
+        let (width, height) = intensities.dimensions();
+        let values = self.scale_grid(width, height, |x, y| intensities.value(x, y));
+
+        Intensities::from_values(width * self.factor(), height * self.factor(), values)
+    }
+
+    fn scale_grid<G>(&self, width: usize, height: usize, get: G) -> Vec<u8>
+    where
+        G: Fn(usize, usize) -> u8
+    {
+        let factor = self.factor();
+        let stride = width * factor;
+        let mut output = vec![0; stride * height * factor];
+
+        // Neighbours outside of the frame are replaced by the closest pixel on the edge.
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, width as isize - 1) as usize;
+            let y = y.clamp(0, height as isize - 1) as usize;
+            get(x, y)
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let block = match self {
+                    Scaler::Nearest(_) => None,
+                    _ => Some(self.block(|dx, dy| at(x as isize + dx, y as isize + dy))),
+                };
+
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let value = match block {
+                            Some(block) => block[dy * factor + dx],
+                            None => get(x, y),
+                        };
+
+                        output[(y * factor + dy) * stride + x * factor + dx] = value;
+                    }
+                }
+            }
+        }
+
+        output
+    }
+
+    /// Compute the output pixels of a single input pixel for the edge-aware scalers, in row
+    /// order, `at` giving access to its neighbours relative to it.
+    fn block<A>(&self, at: A) -> [u8; 9]
+    where
+        A: Fn(isize, isize) -> u8
+    {
+        let p = at(0, 0);
+
+        match self {
+            Scaler::Nearest(_) => [p; 9],
+            Scaler::Epx => {
+                let (a, b, c, d) = (at(0, -1), at(1, 0), at(-1, 0), at(0, 1));
+                let identical = [
+                    a == b && b == c,
+                    a == b && b == d,
+                    a == c && c == d,
+                    b == c && c == d,
+                ];
+
+                if identical.iter().any(|&three| three) {
+                    return [p; 9];
+                }
+
+                [
+                    if c == a { a } else { p },
+                    if a == b { b } else { p },
+                    if d == c { c } else { p },
+                    if b == d { d } else { p },
+                    p, p, p, p, p,
+                ]
+            },
+            Scaler::Scale2x => {
+                let (a, b, c, d) = (at(0, -1), at(1, 0), at(-1, 0), at(0, 1));
+
+                if c != b && a != d {
+                    [
+                        if c == a { c } else { p },
+                        if a == b { b } else { p },
+                        if c == d { c } else { p },
+                        if d == b { b } else { p },
+                        p, p, p, p, p,
+                    ]
+                } else {
+                    [p; 9]
+                }
+            },
+            Scaler::Scale3x => {
+                let (a, b, c) = (at(-1, -1), at(0, -1), at(1, -1));
+                let (d, e, f) = (at(-1, 0), p, at(1, 0));
+                let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+
+                if b != h && d != f {
+                    [
+                        if d == b { d } else { e },
+                        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                        if b == f { f } else { e },
+                        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                        e,
+                        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                        if d == h { d } else { e },
+                        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                        if h == f { f } else { e },
+                    ]
+                } else {
+                    [e; 9]
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(frame: &ScaledFrame) -> Vec<Vec<u8>> {
+        (0..frame.height())
+            .map(|y| (0..frame.width()).map(|x| frame.pixel(x, y)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        let frame = Scaler::Nearest(NonZeroUsize::new(2).unwrap()).scale(&[[true, false]]);
+
+        assert_eq!(pixels(&frame), vec![vec![1, 1, 0, 0], vec![1, 1, 0, 0]]);
+    }
+
+    #[test]
+    fn scale2x_smooths_diagonals() {
+        let screen = [
+            [true, false, false],
+            [false, true, false],
+            [false, false, true],
+        ];
+        let frame = Scaler::Scale2x.scale(&screen);
+
+        // The corners between two diagonal pixels get filled, the isolated ones are kept.
+        assert_eq!(frame.pixel(2, 1), 1);
+        assert_eq!(frame.pixel(1, 2), 1);
+        assert_eq!(frame.pixel(3, 0), 0);
+        assert_eq!(pixels(&frame)[2][2..4], [1, 1]);
+        assert_eq!(Scaler::Epx.scale(&screen), frame);
+    }
+
+    #[test]
+    fn scale3x_keeps_flat_areas() {
+        let frame = Scaler::Scale3x.scale(&[[true; 3]; 3]);
+
+        assert_eq!((frame.width(), frame.height()), (9, 9));
+        assert!(pixels(&frame).iter().flatten().all(|&pixel| pixel == 1));
+    }
+}
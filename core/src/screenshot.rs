@@ -0,0 +1,157 @@
+//! Screenshot encoders.
+//!
+//! The PNG encoder is deliberately minimal: images are stored uncompressed inside the zlib
+//! stream, which keeps it small and dependency free while still producing files every viewer
+//! understands. Netpbm formats are handy for golden tests as they are trivial to compare.
+
+use crate::checksum::{ adler32, crc32_update };
+use crate::render::{ Frame, Renderer };
+
+use alloc::{ format, vec, vec::Vec };
+use core::num::NonZeroUsize;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Encode `frame` as an RGBA PNG, using the scale factor and palette of `renderer`.
+pub fn png<F: Frame + ?Sized>(frame: &F, renderer: &Renderer) -> Vec<u8> {
+    let (width, height) = renderer.dimensions(frame);
+    let pixels = renderer.render(frame);
+
+    encode_png(width, height, &pixels)
+}
+
+/// Encode an RGBA8 buffer of `width` by `height` pixels as a PNG.
+pub fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height * 4, "buffer size doesn't match the image");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, no interlacing.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    // Every scanline starts with its filter type, 0 meaning unfiltered.
+    let mut scanlines = Vec::with_capacity(height * (width * 4 + 1));
+    for line in pixels.chunks_exact((width * 4).max(1)).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(line);
+    }
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut output, b"IHDR", &header);
+    write_chunk(&mut output, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut output, b"IEND", &[]);
+
+    output
+}
+
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    output.extend_from_slice(&crc32_update(crc32_update(0, kind), data).to_be_bytes());
+}
+
+/// Wrap `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+
+        output.push(last as u8);
+        output.extend_from_slice(&length.to_le_bytes());
+        output.extend_from_slice(&(!length).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+/// Encode `frame` as a binary PBM (`P4`) image, upscaled by `scale`.
+///
+/// Lit pixels are written as set bits, which most viewers display in black.
+pub fn pbm<F: Frame + ?Sized>(frame: &F, scale: NonZeroUsize) -> Vec<u8> {
+    let scale = scale.get();
+    let (width, height) = (frame.width() * scale, frame.height() * scale);
+    let mut output = format!("P4\n{} {}\n", width, height).into_bytes();
+
+    for y in 0..height {
+        let mut line = vec![0u8; width.div_ceil(8)];
+
+        for x in 0..width {
+            if frame.pixel(x / scale, y / scale) != 0 {
+                line[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+
+        output.extend_from_slice(&line);
+    }
+
+    output
+}
+
+/// Encode `frame` as a binary PGM (`P5`) image, using the luminance of the palette of
+/// `renderer`.
+pub fn pgm<F: Frame + ?Sized>(frame: &F, renderer: &Renderer) -> Vec<u8> {
+    let (width, height) = renderer.dimensions(frame);
+    let pixels = renderer.render(frame);
+
+    let mut output = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+    output.extend(pixels.chunks_exact(4).map(|pixel| {
+        let luma = 299 * pixel[0] as u32 + 587 * pixel[1] as u32 + 114 * pixel[2] as u32;
+        (luma / 1000) as u8
+    }));
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::crc32;
+    use crate::render::Palette;
+
+    #[test]
+    fn png_structure() {
+        let screen = [[true, false], [false, true]];
+        let image = png(&screen, &Renderer::new(NonZeroUsize::new(3).unwrap(), Palette::DEFAULT));
+
+        assert_eq!(&image[..8], &PNG_SIGNATURE);
+        assert_eq!(&image[12..16], b"IHDR");
+        assert_eq!(&image[16..20], &6u32.to_be_bytes());
+        assert_eq!(&image[20..24], &6u32.to_be_bytes());
+        assert_eq!(&image[29..33], &crc32(&image[12..29]).to_be_bytes());
+        assert_eq!(&image[image.len() - 8..image.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn zlib_blocks() {
+        let data = vec![0x42; 0x10000];
+        let stream = zlib_stored(&data);
+
+        // Header, two block headers, the data and the checksum.
+        assert_eq!(stream.len(), 2 + 5 * 2 + data.len() + 4);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + 0xFFFF], 1);
+    }
+
+    #[test]
+    fn pbm_bits() {
+        let screen = [[true, false, false, false, false, false, false, false, true]];
+
+        let double = NonZeroUsize::new(2).unwrap();
+
+        assert_eq!(pbm(&screen, NonZeroUsize::MIN), b"P4\n9 1\n\x80\x80".to_vec());
+        assert_eq!(&pbm(&screen, double)[8..], b"\xC0\x00\xC0\xC0\x00\xC0");
+    }
+}
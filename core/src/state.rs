@@ -0,0 +1,86 @@
+//! Execution state of a [`Program`](crate::program::Program).
+
+use core::error::Error;
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionState {
+    #[default]
+    Running,
+    /// Blocked on `FX0A` until a key is pressed and released, the key being stored in
+    /// `register`.
+    WaitingForKey { register: u8 },
+    /// The program stopped by itself, it won't execute anything else.
+    Halted(HaltReason),
+    /// The program did something the interpreter can't carry out.
+    Faulted(Fault),
+}
+
+impl ExecutionState {
+    /// Whether instructions are still being executed (or waited upon).
+    pub fn is_running(&self) -> bool {
+        matches!(self, ExecutionState::Running | ExecutionState::WaitingForKey { .. })
+    }
+
+    /// Whether the program is halted or faulted.
+    pub fn is_stopped(&self) -> bool {
+        !self.is_running()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The SCHIP `00FD` instruction was executed.
+    Exit,
+    /// A `1NNN` jumped to itself, the usual way to end a CHIP-8 program.
+    InfiniteLoop { address: u16 },
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltReason::Exit => write!(f, "exited"),
+            HaltReason::InfiniteLoop { address } => {
+                write!(f, "stuck in an infinite loop at {:#05x}", address)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    InvalidInstruction { address: u16, opcode: u16 },
+    /// A subroutine was called with every stack entry already in use.
+    StackOverflow { address: u16 },
+    /// A subroutine returned without being called.
+    StackUnderflow { address: u16 },
+    /// An instruction at `address` accessed the memory outside of its bounds, at `access`.
+    MemoryOutOfBounds { address: u16, access: usize },
+    /// An instruction at `address` wrote to a protected region of the memory, at `access`.
+    ProtectedWrite { address: u16, access: usize },
+    /// No handler took care of the machine code `routine` called at `address`.
+    UnhandledSysCall { address: u16, routine: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::InvalidInstruction { address, opcode } => {
+                write!(f, "invalid instruction {:04x} at {:#05x}", opcode, address)
+            },
+            Fault::StackOverflow { address } => write!(f, "stack overflow at {:#05x}", address),
+            Fault::StackUnderflow { address } => write!(f, "stack underflow at {:#05x}", address),
+            Fault::MemoryOutOfBounds { address, access } => {
+                write!(f, "out of bounds memory access to {:#x} at {:#05x}", access, address)
+            },
+            Fault::ProtectedWrite { address, access } => {
+                write!(f, "write to protected memory at {:#x} at {:#05x}", access, address)
+            },
+            Fault::UnhandledSysCall { address, routine } => {
+                write!(f, "unhandled call to machine code at {:#05x} at {:#05x}", routine, address)
+            },
+        }
+    }
+}
+
+impl Error for Fault {}
@@ -0,0 +1,283 @@
+//! Native handling of `0NNN` machine code routine calls.
+//!
+//! On the COSMAC VIP, `0NNN` runs the CDP1802 machine code found at `NNN`. That code can't be
+//! run by this interpreter, but embedders can register a [`SysHandler`] reproducing what the
+//! routines called by a given ROM do. Calls left unhandled follow the [`SysPolicy`] of the
+//! program.
+//!
+//! Calls the handler doesn't take care of then go to the built-in routines. The VIP interpreter
+//! only had two routines meant to be called by programs, at 0x0E0 and 0x0EE, and their calls are
+//! the `00E0` and `00EE` instructions. The other built-in routines are the ones patched
+//! interpreters added for their platform:
+//!
+//! | Platform                      | Address | Routine                               |
+//! |-------------------------------|---------|---------------------------------------|
+//! | [`Platform::HiresChip8`]      | 0x230   | Clear the 64x64 display               |
+//! | [`Platform::Chip8X`]          | 0x2A0   | Cycle the background colour           |
+//! | [`Platform::HybridVip`]       | any     | Run the machine code on a [`Cdp1802`] |
+//!
+//! Hybrid programs come with their own machine code, which is run as the VIP interpreter would:
+//! the registers, stack and display are written to their [VIP addresses](crate::layout) first,
+//! and the routine starts with R3 as its program counter, R5 holding the CHIP-8 program counter,
+//! R6 and R7 pointing to VX and VY, R8 to the timers and RA being I. It returns to the
+//! interpreter with `SEP R4` (`D4`), everything it changed being read back. A routine that
+//! doesn't return within a second of VIP time is left to the [`SysPolicy`].
+//!
+//! On the other platforms, only a [`SysHandler`] can reproduce machine code written for a given
+//! ROM.
+
+use crate::cdp1802::{ Board, Cdp1802 };
+use crate::keypad::Key;
+use crate::layout;
+use crate::platform::Platform;
+use crate::program::{ Cursor, Program };
+
+#[cfg(feature = "alloc")]
+use alloc::collections::BTreeMap;
+
+/// What to do with `0NNN` calls no handler took care of.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SysPolicy {
+    /// Stop the program with a [`Fault::UnhandledSysCall`](crate::state::Fault).
+    #[default]
+    Fault,
+    /// Skip the call, as most modern interpreters do.
+    Ignore,
+}
+
+#[cfg(feature = "alloc")]
+pub trait SysHandler {
+    /// Handle a call to the routine at `address`, returning how the program counter must move
+    /// afterwards, or `None` if the routine is unknown to this handler.
+    fn call(&mut self, address: u16, program: &mut Program) -> Option<Cursor>;
+}
+
+#[cfg(feature = "alloc")]
+impl<F> SysHandler for F
+where
+    F: FnMut(u16, &mut Program) -> Option<Cursor>
+{
+    fn call(&mut self, address: u16, program: &mut Program) -> Option<Cursor> {
+        self(address, program)
+    }
+}
+
+/// Native replacement of a machine code routine.
+pub type Routine = fn(&mut Program) -> Cursor;
+
+/// Handler dispatching calls to a table of routines indexed by address.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default)]
+pub struct Routines {
+    routines: BTreeMap<u16, Routine>,
+}
+
+#[cfg(feature = "alloc")]
+impl Routines {
+    pub fn new() -> Self {
+        Routines::default()
+    }
+
+    pub fn register(&mut self, address: u16, routine: Routine) -> &mut Self {
+        self.routines.insert(address & 0xFFF, routine);
+        self
+    }
+
+    pub fn unregister(&mut self, address: u16) -> &mut Self {
+        self.routines.remove(&(address & 0xFFF));
+        self
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.routines.contains_key(&(address & 0xFFF))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SysHandler for Routines {
+    fn call(&mut self, address: u16, program: &mut Program) -> Option<Cursor> {
+        self.routines.get(&(address & 0xFFF)).map(|routine| routine(program))
+    }
+}
+
+/// Routines of the platform of `program` that are emulated natively.
+pub(crate) fn builtin(address: u16, program: &mut Program) -> Option<Cursor> {
+    match (program.platform()?, address) {
+        // The patched interpreter of hires ROMs clears its 64x64 display at 0x230.
+        (Platform::HiresChip8, 0x230) => {
+            program.screen.clear();
+            Some(Cursor::Next)
+        },
+        // CHIP-8X cycles the background colour of the colour board at 0x2A0.
+        (Platform::Chip8X, 0x2A0) => {
+            program.colors.cycle_background();
+            Some(Cursor::Next)
+        },
+        (Platform::HybridVip, _) => machine_code(address, program),
+        _ => None,
+    }
+}
+
+/// Machine cycles a routine may take before being given up on, a second of VIP time.
+const ROUTINE_CYCLES: u32 = 60 * 3668;
+
+/// The COSMAC VIP as machine code routines see it: the memory of the program, mirrored every
+/// 4 KiB, and the keypad.
+struct Hybrid<'p, 'a> {
+    program: &'p mut Program<'a>,
+    /// Key selected by `OUT 2`, whose state is reported on EF3.
+    selected_key: Key,
+}
+
+impl Board for Hybrid<'_, '_> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.program.memory()[address as usize % layout::END]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.program.poke(address as usize % layout::END, value);
+    }
+
+    fn input(&mut self, _: u8) -> u8 {
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        if port == 2 {
+            self.selected_key = Key::from_nibble(value);
+        }
+    }
+
+    fn flag(&mut self, flag: u8) -> bool {
+        flag == 3 && self.program.keypad.is_pressed(self.selected_key)
+    }
+}
+
+/// Run the CDP1802 routine at `address` with the registers the VIP interpreter sets up.
+fn machine_code(address: u16, program: &mut Program) -> Option<Cursor> {
+    if program.config.memory_size < layout::END {
+        return None;
+    }
+
+    layout::store(program);
+
+    let mut cpu = Cdp1802::new();
+    let registers = layout::REGISTERS as u16;
+    cpu.r[2] = (layout::STACK_END - 1 - program.stack_pointer as usize * 2) as u16;
+    cpu.r[3] = address;
+    cpu.r[5] = program.program_counter.wrapping_add(2);
+    cpu.r[6] = registers | (address >> 8 & 0xF);
+    cpu.r[7] = registers | (address >> 4 & 0xF);
+    cpu.r[8] = u16::from_be_bytes([program.delay_timer, program.sound_timer]);
+    cpu.r[0xA] = program.i;
+    cpu.r[0xB] = layout::DISPLAY as u16;
+    cpu.p = 3;
+    cpu.x = 2;
+
+    let mut board = Hybrid { program, selected_key: Key::Zero };
+    let mut cycles = 0;
+
+    while cpu.p != 4 {
+        if cycles >= ROUTINE_CYCLES {
+            return None;
+        }
+
+        cycles += cpu.step(&mut board);
+    }
+
+    layout::load(program);
+    let [delay, sound] = cpu.r[8].to_be_bytes();
+    program.delay_timer = delay;
+    program.sound_timer = sound;
+    program.i = cpu.r[0xA];
+
+    Some(Cursor::Jump(cpu.r[5]))
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::config::EmulatorConfig;
+    use crate::program::Register;
+    use crate::state::{ ExecutionState, Fault };
+
+    fn set_v0(program: &mut Program) -> Cursor {
+        program.set_register(Register::V0, 0x42);
+        Cursor::Next
+    }
+
+    #[test]
+    fn routines_and_policies() {
+        let mut program = Program::new();
+        program.load(&[0x03, 0x00, 0x04, 0x00]).unwrap();
+
+        let mut routines = Routines::new();
+        routines.register(0x300, set_v0);
+        program.set_sys_handler(Box::new(routines));
+
+        program.run();
+        assert_eq!(program.register(Register::V0), 0x42);
+
+        // Addresses are masked to 12 bits like those of `0NNN`.
+        let mut routines = Routines::new();
+        routines.register(0x1300, set_v0);
+        assert!(routines.contains(0x300));
+        assert!(routines.call(0xF300, &mut Program::new()).is_some());
+
+        let fault = Fault::UnhandledSysCall { address: 0x202, routine: 0x400 };
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+
+        let mut program = Program::new();
+        program.load(&[0x04, 0x00, 0x00, 0xFD]).unwrap();
+        program.set_sys_policy(SysPolicy::Ignore);
+
+        program.run();
+        assert_eq!(program.program_counter, 0x202);
+    }
+
+    fn hybrid(rom: &[u8]) -> Program<'static> {
+        let mut program = EmulatorConfig::vip().platform(Platform::HybridVip).build().unwrap();
+        program.load(rom).unwrap();
+        program
+    }
+
+    #[test]
+    fn vip_machine_code() {
+        // Call the routine at 0x206, v1 = 7, then loop. The routine stores 0x42 in VX, V2 as
+        // given by its address, sets the delay timer and I, and returns to the interpreter.
+        let mut program = hybrid(&[
+            0x02, 0x06, 0x61, 0x07, 0x12, 0x04,
+            0xF8, 0x42, 0x56, // LDI 0x42, STR R6
+            0xF8, 0x10, 0xB8, // LDI 0x10, PHI R8
+            0xF8, 0x03, 0xBA, // LDI 0x03, PHI RA
+            0xD4,             // SEP R4
+        ]);
+
+        program.run();
+        assert_eq!(program.program_counter, 0x202);
+        assert_eq!(program.register(Register::V2), 0x42);
+        assert_eq!((program.delay_timer, program.i()), (0x10, 0x300));
+        assert_eq!(program.memory()[layout::REGISTERS + 2], 0x42);
+
+        program.run();
+        assert_eq!(program.register(Register::V1), 7);
+    }
+
+    #[test]
+    fn vip_machine_code_that_never_returns() {
+        // The routine at 0x204 branches to itself.
+        let mut program = hybrid(&[0x02, 0x04, 0x00, 0xE0, 0x30, 0x04]);
+        let fault = Fault::UnhandledSysCall { address: 0x200, routine: 0x204 };
+        assert_eq!(program.run(), ExecutionState::Faulted(fault));
+
+        let mut program = hybrid(&[0x02, 0x04, 0x00, 0xE0, 0x30, 0x04]);
+        program.set_sys_policy(SysPolicy::Ignore);
+        program.run();
+        assert_eq!(program.program_counter, 0x202);
+
+        // Other platforms don't run machine code.
+        let mut program = Program::new();
+        program.load(&[0x02, 0x04, 0x00, 0xE0, 0xD4]).unwrap();
+        assert!(program.run().is_stopped());
+    }
+}
@@ -0,0 +1,129 @@
+//! How much of a frame each instruction takes.
+//!
+//! By default, [`Program::run_frame`](crate::program::Program::run_frame) executes a fixed
+//! number of instructions. With [`Timing::Vip`], each instruction is instead charged the machine
+//! cycles the COSMAC VIP interpreter spends on it, and a frame lasts as many cycles as the VIP
+//! runs in 1/60 s, minus what the display steals. The costs come from Laurence Scotford's
+//! analysis of the interpreter and are rounded: they are meant to get the speed of games right,
+//! not to count cycles exactly.
+
+use crate::instructions::Instruction;
+use crate::program::Program;
+
+/// Machine cycles run by the VIP in a 60 Hz frame, at 1.76 MHz and 8 clock cycles per machine
+/// cycle.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// Machine cycles taken every frame by the display DMA, 128 lines of 8 bytes, and by the
+/// interrupt routine updating the timers.
+pub const VIP_INTERRUPT_CYCLES: u32 = 1024 + 46;
+/// Machine cycles spent fetching and decoding every instruction.
+const FETCH: u32 = 40;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// Execute the configured number of instructions per frame.
+    #[default]
+    Instructions,
+    /// Charge each instruction its cost on the COSMAC VIP.
+    Vip,
+}
+
+/// Machine cycles `instruction` takes on the VIP when executed by `program` in its current
+/// state.
+pub fn vip_cycles(instruction: &Instruction, program: &Program) -> u32 {
+    let skip = |skipped: bool| if skipped { 14 } else { 10 };
+
+    let cost = match instruction {
+        // Each of the 256 bytes of the display is cleared by a 12 cycles loop.
+        Instruction::Clear(_) => 24 + 256 * 12,
+        Instruction::ReturnSubroutine(_) => 10,
+        Instruction::Exit(_) | Instruction::Sys(_) => 0,
+        Instruction::JumpTo(_) => 12,
+        Instruction::CallSubroutine(_) => 26,
+        Instruction::SkipEqual(i) => skip(program.v[i.x] == i.value),
+        Instruction::SkipNotEqual(i) => skip(program.v[i.x] != i.value),
+        Instruction::SkipRegisterEqual(i) => 4 + skip(program.v[i.x] == program.v[i.y]),
+        Instruction::SkipRegisterNotEqual(i) => 4 + skip(program.v[i.x] != program.v[i.y]),
+        Instruction::SetRegister(_) => 6,
+        Instruction::AddRegister(_) => 10,
+        Instruction::SetVxToVy(_)
+        | Instruction::SetVxToVxOrVy(_)
+        | Instruction::SetVxToVxAndVy(_)
+        | Instruction::SetVxToVxXorVy(_)
+        | Instruction::SetVxToVxAndVyCarry(_)
+        | Instruction::SetVxToVxSubVy(_)
+        | Instruction::SetVxToVxShr(_)
+        | Instruction::SetVxToVySubVx(_)
+        | Instruction::SetVxToVxShl(_)
+        | Instruction::SetVxToVxAddVyPacked(_) => 44,
+        Instruction::SetIToAddress(_) => 12,
+        Instruction::JumpToPlusV0(_) => 22,
+        Instruction::SetVxToRandomAndValue(_) => 36,
+        Instruction::Draw(draw) => draw_cycles(draw.x, draw.y, draw.n, program),
+        Instruction::SkipKeyPressed(_)
+        | Instruction::SkipKeyNotPressed(_)
+        | Instruction::SkipSecondKeyPressed(_)
+        | Instruction::SkipSecondKeyNotPressed(_) => 14,
+        Instruction::SetVxToDelayTimer(_) => 10,
+        Instruction::SetVxToNextKeyPress(_) => 18,
+        Instruction::SetDelayTimerToVx(_) | Instruction::SetSoundTimerToVx(_) => 10,
+        Instruction::AddVxToI(_) => 16,
+        Instruction::SetIToSpriteLocation(_) | Instruction::SetIToLargeSpriteLocation(_) => 16,
+        // Digits are found by repeated subtractions, each costing 16 cycles.
+        Instruction::StoreBCD(bcd) => {
+            let value = program.v[bcd.x] as u32;
+            84 + (value / 100 + value / 10 % 10 + value % 10) * 16
+        },
+        Instruction::StoreRegisters(i) => 14 + (i.x as u32 + 1) * 14,
+        Instruction::ReadRegisters(i) => 14 + (i.x as u32 + 1) * 14,
+        Instruction::InvalidInstruction(_) => 0,
+    };
+
+    FETCH + cost
+}
+
+/// Cost of `DXYN`: each row of a sprite that isn't aligned on a byte of the display is shifted
+/// across two bytes, which takes longer. Clipped rows aren't drawn.
+fn draw_cycles(x: usize, y: usize, n: usize, program: &Program) -> u32 {
+    let height = program.screen.height();
+    let top = program.v[y] as usize % height;
+    let rows = if program.quirks().wrap { n } else { n.min(height - top) };
+    let aligned = program.v[x].is_multiple_of(8);
+
+    26 + rows as u32 * if aligned { 46 } else { 68 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmulatorConfig;
+    use crate::program::Register;
+
+    #[test]
+    fn data_dependent_costs() {
+        let mut program = Program::new();
+        program.set_register(Register::V0, 8);
+        program.set_register(Register::V1, 199);
+
+        let draw = |x: u16| Instruction::from(0xD005 | x << 8);
+        assert_eq!(vip_cycles(&draw(0), &program), 40 + 26 + 5 * 46);
+        assert_eq!(vip_cycles(&draw(1), &program), 40 + 26 + 5 * 68);
+
+        let bcd = Instruction::from(0xF133);
+        assert_eq!(vip_cycles(&bcd, &program), 40 + 84 + 19 * 16);
+    }
+
+    #[test]
+    fn frames_follow_cycles() {
+        // v0 += 1 and a jump back, 102 cycles per loop: 2598 cycles are left by the display every
+        // frame, enough for 26 increments, the overrun being taken from the next frame.
+        let mut program = EmulatorConfig::vip().timing(Timing::Vip).build().unwrap();
+        program.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        program.run_frame();
+        assert_eq!(program.register(Register::V0), 26);
+
+        program.run_frame();
+        assert_eq!(program.register(Register::V0), 51);
+    }
+}
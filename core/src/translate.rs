@@ -0,0 +1,318 @@
+//! Static recompilation of ROMs into Rust source.
+//!
+//! [`translate`] follows the control flow of a ROM from its start address to find its basic
+//! blocks, and writes a Rust module with one function per block. The module runs a [`Program`]
+//! through its public interface, so it only needs to be linked against this crate, which still
+//! provides the display, keypad and timers:
+//!
+//! ```ignore
+//! let mut program = Program::new();
+//! program.load(ROM)?;
+//!
+//! loop {
+//!     translated::run_frame(&mut program);
+//! }
+//! ```
+//!
+//! Register moves, `I` updates, skips and jumps are translated to plain Rust, the other
+//! instructions being executed by the interpreter of [`instructions`](crate::instructions).
+//! The generated dispatcher matches the program counter against the blocks found, so `BNNN`
+//! jumps and returns land in translated code when they can. Before running, each block checks
+//! that its instructions are still in memory: code the ROM modified, and code that wasn't
+//! found, is interpreted.
+//!
+//! [`Program`]: crate::program::Program
+
+use crate::instructions::Instruction;
+
+use alloc::collections::{ BTreeMap, BTreeSet };
+use alloc::{ format, string::String, vec, vec::Vec };
+
+use core::fmt::Write;
+
+/// An instruction of a block, at `address`.
+struct Op {
+    address: u16,
+    opcode: u16,
+    instruction: Instruction,
+}
+
+/// Addresses execution may continue at after the instruction at `address`, when it ends a
+/// block. `None` if execution goes on with the next instruction in the block.
+fn successors(address: u16, instruction: &Instruction) -> Option<Vec<u16>> {
+    let next = address.wrapping_add(2);
+
+    let successors = match instruction {
+        Instruction::JumpTo(jump) => vec![jump.address],
+        // The return address, where the subroutine comes back.
+        Instruction::CallSubroutine(call) => vec![call.address, next],
+        Instruction::SkipEqual(_)
+        | Instruction::SkipNotEqual(_)
+        | Instruction::SkipRegisterEqual(_)
+        | Instruction::SkipRegisterNotEqual(_)
+        | Instruction::SkipKeyPressed(_)
+        | Instruction::SkipKeyNotPressed(_)
+        | Instruction::SkipSecondKeyPressed(_)
+        | Instruction::SkipSecondKeyNotPressed(_) => vec![next, next.wrapping_add(2)],
+        Instruction::Sys(_) => vec![next],
+        // The targets of BNNN depend on registers, the dispatcher takes care of them.
+        Instruction::JumpToPlusV0(_)
+        | Instruction::ReturnSubroutine(_)
+        | Instruction::Exit(_)
+        | Instruction::InvalidInstruction(_) => vec![],
+        _ => return None,
+    };
+
+    Some(successors)
+}
+
+/// Basic blocks reachable from `start`, by address.
+fn discover(rom: &[u8], start: u16) -> BTreeMap<u16, Vec<Op>> {
+    let fetch = |address: u16| -> Option<u16> {
+        let index = address.checked_sub(start)? as usize;
+        let bytes = rom.get(index..index + 2)?;
+
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+
+    let mut blocks = BTreeMap::new();
+    let mut pending = vec![start];
+    let mut seen = BTreeSet::new();
+
+    while let Some(entry) = pending.pop() {
+        if !seen.insert(entry) {
+            continue;
+        }
+
+        let mut ops = Vec::new();
+        let mut address = entry;
+
+        while let Some(opcode) = fetch(address) {
+            let instruction = Instruction::from(opcode);
+            ops.push(Op { address, opcode, instruction });
+
+            if let Some(successors) = successors(address, &instruction) {
+                pending.extend(successors.into_iter().filter(|&target| fetch(target).is_some()));
+                break;
+            }
+
+            address += 2;
+        }
+
+        if !ops.is_empty() {
+            blocks.insert(entry, ops);
+        }
+    }
+
+    blocks
+}
+
+/// Rust code executing `op`, in a block where `count` instructions were executed once it is.
+/// `changed` tells whether the instructions of the block were modified.
+fn lower(op: &Op, count: usize, changed: &str) -> String {
+    let (address, next) = (op.address, op.address.wrapping_add(2));
+    let leave = format!("*budget -= {}; return;", count);
+    // Instructions ending the block are the last of their function.
+    let end = format!("*budget -= {};", count);
+    let skip = |condition: String| {
+        let skipped = next.wrapping_add(2);
+        format!(
+            "p.program_counter = if {} {{ {:#05x} }} else {{ {:#05x} }}; {}",
+            condition, skipped, next, end
+        )
+    };
+
+    // Registers are named by their `Register` variant, e.g. `Register::VA`.
+    let v = |x: usize| format!("Register::V{:X}", x);
+
+    match op.instruction {
+        Instruction::SetRegister(i) => format!("p.set_register({}, {:#04x});", v(i.x), i.value),
+        Instruction::AddRegister(i) => {
+            format!("p.set_register({0}, p.register({0}).wrapping_add({1:#04x}));", v(i.x), i.value)
+        },
+        Instruction::SetVxToVy(i) => format!("p.set_register({}, p.register({}));", v(i.x), v(i.y)),
+        Instruction::SetIToAddress(i) => format!("p.set_i({:#05x});", i.address),
+        Instruction::AddVxToI(i) => {
+            format!("p.set_i(p.i().wrapping_add(p.register({}) as u16));", v(i.x))
+        },
+        Instruction::SkipEqual(i) => skip(format!("p.register({}) == {:#04x}", v(i.x), i.value)),
+        Instruction::SkipNotEqual(i) => {
+            skip(format!("p.register({}) != {:#04x}", v(i.x), i.value))
+        },
+        Instruction::SkipRegisterEqual(i) => {
+            skip(format!("p.register({}) == p.register({})", v(i.x), v(i.y)))
+        },
+        Instruction::SkipRegisterNotEqual(i) => {
+            skip(format!("p.register({}) != p.register({})", v(i.x), v(i.y)))
+        },
+        // Jumps to themselves halt, and the hires patch is skipped by the interpreter.
+        Instruction::JumpTo(i)
+            if i.address != address && (address, i.address) != (0x200, 0x260) =>
+        {
+            format!("p.program_counter = {:#05x}; {}", i.address, end)
+        },
+        instruction => {
+            let interpret =
+                format!("p.program_counter = {:#05x}; p.run(); // {:04X}", address, op.opcode);
+
+            if successors(address, &instruction).is_some() {
+                return format!("{}\n    {}", interpret, end);
+            }
+
+            let mut code = format!(
+                "{}\n    if p.program_counter != {:#05x} || p.state().is_stopped() {{ {} }}",
+                interpret, next, leave
+            );
+
+            // The instruction may have modified the rest of the block.
+            if matches!(instruction, Instruction::StoreBCD(_) | Instruction::StoreRegisters(_)) {
+                write!(code, "\n    if {} {{ {} }}", changed, leave).unwrap();
+            }
+
+            code
+        },
+    }
+}
+
+/// Whether [`lower`] translates `instruction` to code naming registers, rather than
+/// interpreting it.
+fn names_registers(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SetRegister(_)
+            | Instruction::AddRegister(_)
+            | Instruction::SetVxToVy(_)
+            | Instruction::AddVxToI(_)
+            | Instruction::SkipEqual(_)
+            | Instruction::SkipNotEqual(_)
+            | Instruction::SkipRegisterEqual(_)
+            | Instruction::SkipRegisterNotEqual(_)
+    )
+}
+
+/// Translate `rom`, loaded at `start`, into the source of a Rust module.
+pub fn translate(rom: &[u8], start: u16) -> String {
+    let blocks = discover(rom, start);
+    let mut source = String::new();
+
+    writeln!(
+        source,
+        "//! Translated from a ROM of {} bytes loaded at {:#05x}.",
+        rom.len(),
+        start
+    ).unwrap();
+    let registers = blocks.values().flatten().any(|op| names_registers(&op.instruction));
+    source.push_str(if registers {
+        "\nuse chip8_core::program::{ Program, Register };\n"
+    } else {
+        "\nuse chip8_core::program::Program;\n"
+    });
+    source.push_str(concat!(
+        "use chip8_core::state::ExecutionState;\n",
+        "\n",
+        "/// Execute at most `limit` instructions, stopping early if the program halts or ",
+        "faults.\n",
+        "pub fn run(p: &mut Program, limit: usize) -> ExecutionState {\n",
+        "    let mut budget = limit;\n",
+        "\n",
+        "    while budget > 0 && !p.state().is_stopped() {\n",
+        "        match p.program_counter {\n",
+    ));
+
+    for address in blocks.keys() {
+        writeln!(source, "            {:#05x} => block_{:03x}(p, &mut budget),", address, address)
+            .unwrap();
+    }
+
+    source.push_str(concat!(
+        "            _ => interpret(p, &mut budget),\n",
+        "        }\n",
+        "    }\n",
+        "\n",
+        "    p.state()\n",
+        "}\n",
+        "\n",
+        "/// Execute the instructions of one 60 Hz frame, then decrement the timers.\n",
+        "pub fn run_frame(p: &mut Program) -> ExecutionState {\n",
+        "    run(p, p.config().get_instructions_per_frame());\n",
+        "    p.decrement_timers();\n",
+        "\n",
+        "    p.state()\n",
+        "}\n",
+        "\n",
+        "/// Execute one instruction with the interpreter.\n",
+        "fn interpret(p: &mut Program, budget: &mut usize) {\n",
+        "    p.run();\n",
+        "    *budget -= 1;\n",
+        "}\n",
+    ));
+
+    for (address, ops) in &blocks {
+        let end = address + ops.len() as u16 * 2;
+        let index = (address - start) as usize;
+        let bytes = &rom[index..index + ops.len() * 2];
+        let changed = format!(
+            "p.memory().get({:#05x}..{:#05x}) != Some(&BLOCK_{:03X}[..])",
+            address, end, address
+        );
+
+        let bytes: Vec<_> = bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+        write!(source, "\nconst BLOCK_{:03X}: [u8; {}] = [", address, bytes.len()).unwrap();
+        writeln!(source, "{}];", bytes.join(", ")).unwrap();
+        writeln!(source, "\nfn block_{:03x}(p: &mut Program, budget: &mut usize) {{", address)
+            .unwrap();
+        writeln!(source, "    if *budget < {} || {} {{", ops.len(), changed).unwrap();
+        source.push_str("        return interpret(p, budget);\n    }\n\n");
+
+        for (count, op) in ops.iter().enumerate() {
+            writeln!(source, "    {}", lower(op, count + 1, &changed)).unwrap();
+        }
+
+        let last = ops.last().expect("blocks aren't empty");
+        if successors(last.address, &last.instruction).is_none() {
+            writeln!(source, "    p.program_counter = {:#05x}; *budget -= {};", end, ops.len())
+                .unwrap();
+        }
+
+        source.push_str("}\n");
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovers_blocks() {
+        let rom = [
+            0x60, 0x05, 0x22, 0x08, // v0 = 5, call 0x208
+            0x12, 0x04, 0x00, 0x00, // jump to itself, data
+            0x30, 0x05, 0xB2, 0x00, // skip if v0 == 5, jump to 0x200 + v0
+            0x00, 0xEE,             // return
+        ];
+        let blocks = discover(&rom, 0x200);
+
+        let starts: Vec<_> = blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x204, 0x208, 0x20A, 0x20C]);
+        assert_eq!(blocks[&0x200].len(), 2);
+    }
+
+    #[test]
+    fn generated_source() {
+        let source = translate(&[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00], 0x200);
+
+        assert!(source.contains("0x200 => block_200(p, &mut budget),"));
+        assert!(source.contains("const BLOCK_200: [u8; 8] = [0x60, 0x05, 0xa3, 0x00,"));
+        assert!(source.contains("use chip8_core::program::{ Program, Register };"));
+        assert!(source.contains("p.set_register(Register::V0, 0x05);"));
+        assert!(source.contains("p.set_i(0x300);"));
+        assert!(source.contains("p.program_counter = 0x204; p.run(); // F055"));
+        assert!(source.contains("p.program_counter = 0x200; *budget -= 4;\n}"));
+
+        // Registers are only imported when they are named.
+        let source = translate(&[0x00, 0xE0, 0x12, 0x02], 0x200);
+        assert!(source.contains("use chip8_core::program::Program;"));
+    }
+}
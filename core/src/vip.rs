@@ -0,0 +1,359 @@
+//! Low-level emulation of the COSMAC VIP, running the original CHIP-8 interpreter.
+//!
+//! Instead of interpreting CHIP-8 instructions, a [`Vip`] emulates the machine they were
+//! designed for: a [`Cdp1802`] CPU with 4 KiB of RAM, the 512 bytes monitor ROM, the CDP1861
+//! video chip and the hex keypad. Neither the monitor nor the interpreter are distributed with
+//! this crate, their images must be supplied by the user.
+//!
+//! The interpreter keeps its state in the last page of RAM, which the inspection methods read:
+//! V0 to VF at 0xEF0, the display at 0xF00. I is R(A), the program counter R5, and the delay and
+//! sound timers R8.1 and R8.0.
+
+use crate::cdp1802::{ Board, Cdp1802 };
+use crate::checksum;
+use crate::display::Display;
+use crate::keypad::{ Key, Keypad };
+use crate::program::Register;
+use crate::rom::{ LoadError, LoadInfo };
+use crate::state::ExecutionState;
+
+/// Size of the RAM, mirrored up to 0x7FFF.
+pub const RAM_SIZE: usize = 0x1000;
+/// Size of the monitor ROM, mirrored from 0x8000 to 0xFFFF.
+pub const ROM_SIZE: usize = 0x200;
+/// Where CHIP-8 programs are loaded, after the interpreter.
+pub const START_ADDRESS: usize = 0x200;
+/// Start of the memory used by the interpreter: stack, registers and display.
+const RESERVED: usize = RAM_SIZE - 0x160;
+
+/// Machine cycles of a display line of the CDP1861.
+const LINE_CYCLES: u32 = 14;
+/// Lines of a 60 Hz frame.
+const LINES: u32 = 262;
+/// First line shown, 128 lines being shown.
+const DISPLAY_START: u32 = 80;
+const DISPLAY_END: u32 = DISPLAY_START + 128;
+/// Lines before the display during which the interrupt is requested.
+const INTERRUPT_LINES: u32 = 2;
+/// Lines before the beginning and the end of the display during which EF1 is asserted.
+const EF1_LINES: u32 = 4;
+/// Bytes of a line, sent by DMA at the start of each displayed line.
+const LINE_BYTES: usize = 8;
+
+/// Memory and devices of the VIP.
+struct Hardware {
+    ram: [u8; RAM_SIZE],
+    rom: [u8; ROM_SIZE],
+    /// The ROM shows at every address after a reset, until an address with A15 set is accessed.
+    boot: bool,
+    keypad: Keypad,
+    /// Key selected by `OUT 2`, whose state is reported on EF3.
+    selected_key: Key,
+    display_on: bool,
+    /// Current line of the CDP1861, for EF1.
+    line: u32,
+}
+
+impl Board for Hardware {
+    fn read(&mut self, address: u16) -> u8 {
+        if address & 0x8000 != 0 {
+            self.boot = false;
+        }
+
+        if self.boot || address & 0x8000 != 0 {
+            self.rom[address as usize % ROM_SIZE]
+        } else {
+            self.ram[address as usize % RAM_SIZE]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if address & 0x8000 == 0 {
+            self.ram[address as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        // The data bus floats, nothing drives it on input.
+        if port == 1 {
+            self.display_on = true;
+        }
+
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.selected_key = Key::from_nibble(value),
+            _ => {},
+        }
+    }
+
+    fn flag(&mut self, flag: u8) -> bool {
+        match flag {
+            1 => {
+                let before = |line: u32| (line - EF1_LINES..line).contains(&self.line);
+                self.display_on && (before(DISPLAY_START) || before(DISPLAY_END))
+            },
+            3 => self.keypad.is_pressed(self.selected_key),
+            _ => false,
+        }
+    }
+}
+
+pub struct Vip {
+    cpu: Cdp1802,
+    hardware: Hardware,
+    /// The interpreter, in its first `interpreter_length` bytes.
+    interpreter: [u8; START_ADDRESS],
+    interpreter_length: usize,
+    /// Machine cycle within the current frame.
+    cycle: u32,
+    /// Bytes sent by DMA for each displayed line of the current frame.
+    lines: [[u8; LINE_BYTES]; 128],
+    screen: Display,
+}
+
+impl Vip {
+    /// A VIP with the `monitor` ROM, and the CHIP-8 `interpreter` loaded at address 0.
+    pub fn new(monitor: &[u8], interpreter: &[u8]) -> Result<Self, LoadError> {
+        if monitor.is_empty() || interpreter.is_empty() {
+            return Err(LoadError::Empty);
+        }
+
+        if monitor.len() > ROM_SIZE {
+            return Err(LoadError::TooLarge { size: monitor.len(), available: ROM_SIZE });
+        }
+
+        if interpreter.len() > START_ADDRESS {
+            return Err(LoadError::TooLarge { size: interpreter.len(), available: START_ADDRESS });
+        }
+
+        let mut rom = [0; ROM_SIZE];
+        rom[..monitor.len()].copy_from_slice(monitor);
+
+        let mut copy = [0; START_ADDRESS];
+        copy[..interpreter.len()].copy_from_slice(interpreter);
+
+        let mut vip = Vip {
+            cpu: Cdp1802::new(),
+            hardware: Hardware {
+                ram: [0; RAM_SIZE],
+                rom,
+                boot: true,
+                keypad: Keypad::default(),
+                selected_key: Key::Zero,
+                display_on: false,
+                line: 0,
+            },
+            interpreter: copy,
+            interpreter_length: interpreter.len(),
+            cycle: 0,
+            lines: [[0; LINE_BYTES]; 128],
+            screen: Display::new(64, 32),
+        };
+        vip.reset();
+
+        Ok(vip)
+    }
+
+    /// Clear the RAM, reload the interpreter and reset the CPU, which boots from the monitor.
+    pub fn reset(&mut self) {
+        self.hardware.ram = [0; RAM_SIZE];
+        let length = self.interpreter_length;
+        self.hardware.ram[..length].copy_from_slice(&self.interpreter[..length]);
+        self.hardware.boot = true;
+        self.hardware.display_on = false;
+        self.cpu.reset();
+        self.cycle = 0;
+        self.screen.clear();
+    }
+
+    /// Reset the machine and load `rom` at 0x200, where the interpreter starts running it.
+    pub fn load(&mut self, rom: &[u8]) -> Result<LoadInfo, LoadError> {
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+
+        let available = RESERVED - START_ADDRESS;
+        if rom.len() > available {
+            return Err(LoadError::TooLarge { size: rom.len(), available });
+        }
+
+        self.reset();
+        self.hardware.ram[START_ADDRESS..START_ADDRESS + rom.len()].copy_from_slice(rom);
+
+        Ok(LoadInfo {
+            address: START_ADDRESS as u16,
+            size: rom.len(),
+            crc32: checksum::crc32(rom),
+            sha1: checksum::sha1(rom)
+        })
+    }
+
+    /// Run the CPU for the machine cycles of one frame of the CDP1861, interleaved with the
+    /// display interrupt and DMA.
+    pub fn run_frame(&mut self) -> ExecutionState {
+        let mut next_line = DISPLAY_START;
+        let mut interrupted = false;
+
+        while self.cycle < LINES * LINE_CYCLES {
+            let line = self.cycle / LINE_CYCLES;
+            self.hardware.line = line;
+
+            if !self.hardware.display_on {
+                self.cycle += self.cpu.step(&mut self.hardware);
+                continue;
+            }
+
+            if !interrupted && (DISPLAY_START - INTERRUPT_LINES..DISPLAY_START).contains(&line) {
+                interrupted = self.cpu.interrupt();
+
+                if interrupted {
+                    self.cycle += 1;
+                    continue;
+                }
+            }
+
+            if (DISPLAY_START..DISPLAY_END).contains(&line) && next_line <= line {
+                let bytes = &mut self.lines[(line - DISPLAY_START) as usize];
+                for byte in bytes.iter_mut() {
+                    *byte = self.cpu.dma_out(&mut self.hardware);
+                }
+
+                next_line = line + 1;
+                self.cycle += LINE_BYTES as u32;
+                continue;
+            }
+
+            self.cycle += self.cpu.step(&mut self.hardware);
+        }
+
+        self.cycle -= LINES * LINE_CYCLES;
+        self.update_screen(next_line > DISPLAY_START);
+
+        ExecutionState::Running
+    }
+
+    /// Show the lines sent during the frame, each CHIP-8 row being 4 lines high.
+    fn update_screen(&mut self, displayed: bool) {
+        self.screen.clear();
+
+        if !displayed {
+            return;
+        }
+
+        for y in 0..self.screen.height() {
+            let line = self.lines[y * 128 / self.screen.height()];
+
+            for x in 0..self.screen.width() {
+                if (line[x / 8] >> (7 - x % 8)) & 1 == 1 {
+                    self.screen.set(x, y, true);
+                }
+            }
+        }
+    }
+
+    pub fn cpu(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    /// The RAM.
+    pub fn memory(&self) -> &[u8] {
+        &self.hardware.ram
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.hardware.ram
+    }
+
+    /// What the CDP1861 showed during the last frame.
+    pub fn screen(&self) -> &Display {
+        &self.screen
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.hardware.keypad
+    }
+
+    pub fn keydown(&mut self, key: Key) {
+        self.hardware.keypad.press(key);
+    }
+
+    pub fn keyup(&mut self, key: Key) {
+        self.hardware.keypad.release(key);
+    }
+
+    /// Whether the beeper is on.
+    pub fn sound(&self) -> bool {
+        self.cpu.q
+    }
+
+    pub fn register(&self, x: Register) -> u8 {
+        self.hardware.ram[RAM_SIZE - 0x110 + x.index()]
+    }
+
+    pub fn i(&self) -> u16 {
+        self.cpu.r[0xA]
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.cpu.r[5]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        (self.cpu.r[8] >> 8) as u8
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.r[8] as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Jumps to 0x8003 to leave the boot mode, then runs the RAM from 0 with R3.
+    const MONITOR: [u8; 8] = [0xC0, 0x80, 0x03, 0xF8, 0x00, 0xB3, 0xA3, 0xD3];
+
+    /// Turns the display on and waits, the interrupt routine pointing R0 to 0xF00.
+    const INTERPRETER: [u8; 0x2A] = [
+        0xF8, 0x00, 0xB2, 0xF8, 0xF0, 0xA2, // R2 = 0x00F0
+        0xF8, 0x00, 0xB1, 0xF8, 0x20, 0xA1, // R1 = 0x0020
+        0xE2, 0x69,                         // SEX 2, INP 1
+        0x3E, 0x0E,                         // BN3 0E
+        0x7B, 0x30, 0x10,                   // SEQ, BR 10
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0x70,                               // RET
+        0x22, 0x78,                         // DEC R2, SAV
+        0xF8, 0x0F, 0xB0, 0xF8, 0x00, 0xA0, // R0 = 0x0F00
+        0x30, 0x1F,                         // BR 1F
+    ];
+
+    #[test]
+    fn boots_and_displays() {
+        let mut vip = Vip::new(&MONITOR, &INTERPRETER).unwrap();
+        vip.load(&[0x12, 0x00]).unwrap();
+        vip.memory_mut()[0xF00] = 0xF0;
+        vip.memory_mut()[0xF00 + 8 * 4] = 0x01;
+
+        vip.run_frame();
+
+        assert_eq!(vip.memory()[0x200], 0x12);
+        assert!(vip.screen().get(0, 0) && vip.screen().get(3, 0) && !vip.screen().get(4, 0));
+        assert!(vip.screen().get(7, 1));
+        assert!(!vip.sound());
+
+        vip.keydown(Key::Zero);
+        vip.run_frame();
+        assert!(vip.sound());
+    }
+
+    #[test]
+    fn rejects_large_images() {
+        let error = Vip::new(&[0; 0x201], &INTERPRETER).err().unwrap();
+        assert!(matches!(error, LoadError::TooLarge { size: 0x201, available: 0x200 }));
+    }
+}
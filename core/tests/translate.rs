@@ -0,0 +1,53 @@
+//! The translated module checked in `translated/mod.rs`, generated with
+//! `chip8 sprite.ch8 --translate core/tests/translated/mod.rs`, runs as the interpreter does.
+
+mod translated;
+
+use chip8_core::program::{ Program, Register };
+use chip8_core::translate;
+
+/// Draws a sprite moving across the screen, counting with BCD in a subroutine, forever.
+const ROM: [u8; 26] = [
+    0x66, 0x00, 0x67, 0x00, // v6 = 0, v7 = 0
+    0xA2, 0x18, 0xD6, 0x72, // I = sprite, draw at (v6, v7)
+    0x22, 0x10, 0x76, 0x01, // call 0x210, v6 += 1
+    0x77, 0x01, 0x12, 0x04, // v7 += 1, jump 0x204
+    0xA3, 0x00, 0xF6, 0x33, // I = 0x300, BCD of v6
+    0xF2, 0x65, 0x00, 0xEE, // read the digits to v0..v2, return
+    0xF0, 0x90,             // sprite
+];
+
+#[test]
+fn fixture_is_up_to_date() {
+    assert_eq!(translate::translate(&ROM, 0x200), include_str!("translated/mod.rs"));
+}
+
+#[test]
+fn matches_interpreter() {
+    let load = || {
+        let mut program = Program::new();
+        program.load(&ROM).unwrap();
+        program
+    };
+    let (mut interpreted, mut translated) = (load(), load());
+
+    for frame in 0..100 {
+        interpreted.run_frame();
+        translated::run_frame(&mut translated);
+
+        assert_eq!(interpreted.state(), translated.state(), "frame {}", frame);
+        assert_eq!(interpreted.program_counter, translated.program_counter, "frame {}", frame);
+        assert_eq!(interpreted.i(), translated.i(), "frame {}", frame);
+        assert_eq!(interpreted.screen, translated.screen, "frame {}", frame);
+        assert!(interpreted.memory() == translated.memory(), "frame {}", frame);
+
+        for register in Register::ALL {
+            assert_eq!(interpreted.register(register), translated.register(register));
+        }
+    }
+
+    // The sprite moved, and the digits of its position before the last move were read back.
+    assert_eq!(translated.register(Register::V6), 100);
+    let digits = [Register::V0, Register::V1, Register::V2].map(|x| translated.register(x));
+    assert_eq!(digits, [0, 9, 9]);
+}
@@ -0,0 +1,94 @@
+//! Translated from a ROM of 26 bytes loaded at 0x200.
+
+use chip8_core::program::{ Program, Register };
+use chip8_core::state::ExecutionState;
+
+/// Execute at most `limit` instructions, stopping early if the program halts or faults.
+pub fn run(p: &mut Program, limit: usize) -> ExecutionState {
+    let mut budget = limit;
+
+    while budget > 0 && !p.state().is_stopped() {
+        match p.program_counter {
+            0x200 => block_200(p, &mut budget),
+            0x204 => block_204(p, &mut budget),
+            0x20a => block_20a(p, &mut budget),
+            0x210 => block_210(p, &mut budget),
+            _ => interpret(p, &mut budget),
+        }
+    }
+
+    p.state()
+}
+
+/// Execute the instructions of one 60 Hz frame, then decrement the timers.
+pub fn run_frame(p: &mut Program) -> ExecutionState {
+    run(p, p.config().get_instructions_per_frame());
+    p.decrement_timers();
+
+    p.state()
+}
+
+/// Execute one instruction with the interpreter.
+fn interpret(p: &mut Program, budget: &mut usize) {
+    p.run();
+    *budget -= 1;
+}
+
+const BLOCK_200: [u8; 10] = [0x66, 0x00, 0x67, 0x00, 0xa2, 0x18, 0xd6, 0x72, 0x22, 0x10];
+
+fn block_200(p: &mut Program, budget: &mut usize) {
+    if *budget < 5 || p.memory().get(0x200..0x20a) != Some(&BLOCK_200[..]) {
+        return interpret(p, budget);
+    }
+
+    p.set_register(Register::V6, 0x00);
+    p.set_register(Register::V7, 0x00);
+    p.set_i(0x218);
+    p.program_counter = 0x206; p.run(); // D672
+    if p.program_counter != 0x208 || p.state().is_stopped() { *budget -= 4; return; }
+    p.program_counter = 0x208; p.run(); // 2210
+    *budget -= 5;
+}
+
+const BLOCK_204: [u8; 6] = [0xa2, 0x18, 0xd6, 0x72, 0x22, 0x10];
+
+fn block_204(p: &mut Program, budget: &mut usize) {
+    if *budget < 3 || p.memory().get(0x204..0x20a) != Some(&BLOCK_204[..]) {
+        return interpret(p, budget);
+    }
+
+    p.set_i(0x218);
+    p.program_counter = 0x206; p.run(); // D672
+    if p.program_counter != 0x208 || p.state().is_stopped() { *budget -= 2; return; }
+    p.program_counter = 0x208; p.run(); // 2210
+    *budget -= 3;
+}
+
+const BLOCK_20A: [u8; 6] = [0x76, 0x01, 0x77, 0x01, 0x12, 0x04];
+
+fn block_20a(p: &mut Program, budget: &mut usize) {
+    if *budget < 3 || p.memory().get(0x20a..0x210) != Some(&BLOCK_20A[..]) {
+        return interpret(p, budget);
+    }
+
+    p.set_register(Register::V6, p.register(Register::V6).wrapping_add(0x01));
+    p.set_register(Register::V7, p.register(Register::V7).wrapping_add(0x01));
+    p.program_counter = 0x204; *budget -= 3;
+}
+
+const BLOCK_210: [u8; 8] = [0xa3, 0x00, 0xf6, 0x33, 0xf2, 0x65, 0x00, 0xee];
+
+fn block_210(p: &mut Program, budget: &mut usize) {
+    if *budget < 4 || p.memory().get(0x210..0x218) != Some(&BLOCK_210[..]) {
+        return interpret(p, budget);
+    }
+
+    p.set_i(0x300);
+    p.program_counter = 0x212; p.run(); // F633
+    if p.program_counter != 0x214 || p.state().is_stopped() { *budget -= 2; return; }
+    if p.memory().get(0x210..0x218) != Some(&BLOCK_210[..]) { *budget -= 2; return; }
+    p.program_counter = 0x214; p.run(); // F265
+    if p.program_counter != 0x216 || p.state().is_stopped() { *budget -= 3; return; }
+    p.program_counter = 0x216; p.run(); // 00EE
+    *budget -= 4;
+}
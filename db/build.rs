@@ -0,0 +1,27 @@
+//! Picks the database embedded by the crate: the snapshot of `data`, or the files of an upstream
+//! checkout whose `database` directory is given by `CHIP8_DATABASE_DIR`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const FILES: [&str; 2] = ["programs.json", "sha1-hashes.json"];
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=CHIP8_DATABASE_DIR");
+
+    let source = match env::var_os("CHIP8_DATABASE_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None => PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("data"),
+    };
+    let destination = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    for file in FILES.iter() {
+        let path = source.join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        if let Err(error) = fs::copy(&path, destination.join(file)) {
+            panic!("couldn't copy the database file {}: {}", path.display(), error);
+        }
+    }
+}
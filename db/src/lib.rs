@@ -0,0 +1,442 @@
+//! Metadata of known ROMs, in the format of the community CHIP-8 database.
+//!
+//! ROMs are identified by the SHA-1 of their content. The embedded database comes from the
+//! `db/data` directory, which `scripts/update-database` refreshes from the upstream
+//! repository. The files checked in only hold a few entries: to embed the whole database, run
+//! the script, or build with `CHIP8_DATABASE_DIR` set to the `database` directory of a checkout
+//! of the upstream repository.
+
+use chip8_core::checksum;
+use chip8_core::config::EmulatorConfig;
+use chip8_core::font::Font;
+use chip8_core::keypad::{ GamepadButton, GamepadMapping, Key };
+use chip8_core::platform::Platform;
+use chip8_core::program::Program;
+use chip8_core::quirks::Quirks;
+use chip8_core::render::{ Color, Palette };
+use chip8_core::rom::{ LoadError, LoadInfo };
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::sync::OnceLock;
+
+const PROGRAMS: &str = include_str!(concat!(env!("OUT_DIR"), "/programs.json"));
+const HASHES: &str = include_str!(concat!(env!("OUT_DIR"), "/sha1-hashes.json"));
+
+#[derive(Deserialize)]
+struct ProgramEntry {
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    file: Option<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    #[serde(default)]
+    tickrate: Option<usize>,
+    #[serde(default)]
+    start_address: Option<u16>,
+    #[serde(default)]
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    font_style: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// Key of the keypad used for an action of a game, such as `up` or `a`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyHelp {
+    pub action: String,
+    pub key: Key,
+}
+
+/// What the database knows about a ROM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub authors: Vec<String>,
+    /// Name of the ROM file, when the program has several versions.
+    pub file: Option<String>,
+    /// Platforms the ROM runs on, the first being the preferred one.
+    pub platforms: Vec<Platform>,
+    /// Quirks of the preferred platform, adjusted for this ROM.
+    pub quirks: Quirks,
+    /// Instructions to execute per frame.
+    pub tickrate: Option<usize>,
+    pub start_address: Option<u16>,
+    pub palette: Option<Palette>,
+    pub keys: Vec<KeyHelp>,
+    pub font: Option<Font>,
+}
+
+impl RomInfo {
+    pub fn platform(&self) -> Option<Platform> {
+        self.platforms.first().copied()
+    }
+
+    /// Apply the platform, quirks, tick rate, start address and font of the ROM to `config`.
+    pub fn configure(&self, mut config: EmulatorConfig) -> EmulatorConfig {
+        if let Some(platform) = self.platform() {
+            config = config.platform(platform);
+        }
+
+        config = config.quirks(self.quirks);
+
+        if let Some(tickrate) = self.tickrate {
+            config = config.instructions_per_frame(tickrate);
+        }
+
+        if let Some(address) = self.start_address {
+            config = config.start_address(address);
+        }
+
+        if let Some(font) = self.font {
+            config = config.font(font);
+        }
+
+        config
+    }
+
+    /// Gamepad bindings for the directions and the `a` and `b` actions of the ROM, if it has
+    /// any.
+    pub fn gamepad_mapping(&self) -> Option<GamepadMapping> {
+        let mut mapping = GamepadMapping::empty();
+        let mut bound = false;
+
+        for help in &self.keys {
+            let button = match help.action.as_str() {
+                "up" => GamepadButton::Up,
+                "down" => GamepadButton::Down,
+                "left" => GamepadButton::Left,
+                "right" => GamepadButton::Right,
+                "a" => GamepadButton::South,
+                "b" => GamepadButton::East,
+                _ => continue,
+            };
+
+            mapping.bind(button, help.key);
+            bound = true;
+        }
+
+        if bound { Some(mapping) } else { None }
+    }
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Json(serde_json::Error),
+    /// A hash refers to a program that doesn't exist.
+    InvalidIndex { sha1: String, index: usize },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Json(error) => write!(f, "invalid database: {}", error),
+            DatabaseError::InvalidIndex { sha1, index } => {
+                write!(f, "hash {} refers to the missing program {}", sha1, index)
+            },
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DatabaseError::Json(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(error: serde_json::Error) -> Self {
+        DatabaseError::Json(error)
+    }
+}
+
+pub struct Database {
+    programs: Vec<ProgramEntry>,
+    /// Index in `programs` of the program each lowercase SHA-1 belongs to.
+    hashes: HashMap<String, usize>,
+}
+
+impl Database {
+    /// The database shipped with the crate, parsed on first use.
+    pub fn embedded() -> &'static Database {
+        static DATABASE: OnceLock<Database> = OnceLock::new();
+
+        DATABASE.get_or_init(|| {
+            Database::from_json(PROGRAMS, HASHES).expect("embedded database is valid")
+        })
+    }
+
+    /// Parse the `programs.json` and `sha1-hashes.json` files of the community database.
+    pub fn from_json(programs: &str, hashes: &str) -> Result<Self, DatabaseError> {
+        let programs: Vec<ProgramEntry> = serde_json::from_str(programs)?;
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes)?;
+
+        if let Some((sha1, index)) = hashes.iter().find(|(_, index)| **index >= programs.len()) {
+            return Err(DatabaseError::InvalidIndex { sha1: sha1.clone(), index: *index });
+        }
+
+        let hashes = hashes.into_iter()
+            .map(|(sha1, index)| (sha1.to_lowercase(), index))
+            .collect();
+
+        Ok(Database { programs, hashes })
+    }
+
+    /// Number of known ROMs.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Information about the ROM with the given hexadecimal SHA-1.
+    pub fn lookup(&self, sha1: &str) -> Option<RomInfo> {
+        let sha1 = sha1.to_lowercase();
+        let program = &self.programs[*self.hashes.get(&sha1)?];
+        let rom = program.roms.iter()
+            .find(|(hash, _)| hash.to_lowercase() == sha1)
+            .map(|(_, rom)| rom);
+
+        let mut info = RomInfo {
+            title: program.title.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            authors: program.authors.clone(),
+            file: None,
+            platforms: Vec::new(),
+            quirks: Quirks::default(),
+            tickrate: None,
+            start_address: None,
+            palette: None,
+            keys: Vec::new(),
+            font: None,
+        };
+
+        if let Some(rom) = rom {
+            info.file = rom.file.clone();
+            info.platforms = rom.platforms.iter().filter_map(|id| Platform::from_id(id)).collect();
+            info.tickrate = rom.tickrate.filter(|tickrate| *tickrate > 0);
+            info.start_address = rom.start_address;
+            info.palette = rom.colors.as_ref().and_then(|colors| palette(&colors.pixels));
+            info.font = rom.font_style.as_deref().and_then(Font::from_name);
+
+            info.keys = rom.keys.iter()
+                .filter_map(|(action, key)| {
+                    let key = Key::try_from(*key).ok()?;
+                    Some(KeyHelp { action: action.clone(), key })
+                })
+                .collect();
+            info.keys.sort_by(|a, b| a.action.cmp(&b.action));
+
+            if let Some(platform) = info.platform() {
+                info.quirks = platform.quirks();
+
+                for (name, value) in rom.quirky_platforms.get(platform.id()).into_iter().flatten() {
+                    info.quirks.set(name, *value);
+                }
+            }
+        }
+
+        Some(info)
+    }
+
+    /// Information about `rom`, identified by its SHA-1.
+    pub fn identify(&self, rom: &[u8]) -> Option<RomInfo> {
+        self.lookup(&checksum::to_hex(&checksum::sha1(rom)))
+    }
+
+    /// Load `rom` in `program`, applying the platform, quirks, tick rate, start address and font
+    /// recommended for it if it is known, as [`RomInfo::configure`] does.
+    ///
+    /// What the previous ROM needed is forgotten: the program goes back to the settings it was
+    /// built with, for unknown ROMs as for what the database doesn't say about known ones.
+    pub fn load(&self, program: &mut Program, rom: &[u8])
+        -> Result<(LoadInfo, Option<RomInfo>), LoadError>
+    {
+        let info = self.identify(rom);
+        program.restore_platform();
+
+        if let Some(info) = &info {
+            if let Some(platform) = info.platform() {
+                program.set_platform(platform);
+            }
+
+            program.set_quirks(info.quirks);
+
+            if let Some(tickrate) = info.tickrate {
+                program.set_instructions_per_frame(tickrate);
+            }
+
+            if let Some(font) = info.font {
+                program.set_font(font);
+            }
+        }
+
+        let load = match info.as_ref().and_then(|info| info.start_address) {
+            Some(address) => program.load_at(address, rom)?,
+            None => program.load(rom)?,
+        };
+
+        Ok((load, info))
+    }
+}
+
+/// Palette from the `#RRGGBB` colours of the database: background and foreground, optionally
+/// followed by the colours of the second plane and of both planes.
+fn palette(pixels: &[String]) -> Option<Palette> {
+    let colors = pixels.iter()
+        .map(|color| u32::from_str_radix(color.trim_start_matches('#'), 16).ok().map(Color::from_hex))
+        .collect::<Option<Vec<_>>>()?;
+
+    match colors.as_slice() {
+        [background, foreground] => Some(Palette::monochrome(*background, *foreground)),
+        [a, b, c, d, ..] => Some(Palette::new([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 4] = [0x60, 0x01, 0x12, 0x02];
+
+    fn database() -> Database {
+        let sha1 = checksum::to_hex(&checksum::sha1(&ROM)).to_uppercase();
+        let programs = format!(r##"[
+            {{
+                "title": "Test",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "file": "test.ch8",
+                        "platforms": ["superchip", "xochip"],
+                        "quirkyPlatforms": {{ "superchip": {{ "jump": false }} }},
+                        "tickrate": 30,
+                        "colors": {{ "pixels": ["#000000", "#ff8000"] }},
+                        "keys": {{ "up": 5, "a": 6 }}
+                    }}
+                }}
+            }}
+        ]"##, sha1);
+        let hashes = format!(r#"{{ "{}": 0 }}"#, sha1);
+
+        Database::from_json(&programs, &hashes).unwrap()
+    }
+
+    #[test]
+    fn lookup_by_hash() {
+        let info = database().identify(&ROM).unwrap();
+
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.platforms, vec![Platform::Superchip, Platform::XoChip]);
+        assert_eq!(info.quirks, Quirks { jump: false, ..Platform::Superchip.quirks() });
+        assert_eq!(info.palette, Some(Palette::monochrome(Color::BLACK, Color::from_hex(0xFF8000))));
+        assert_eq!(info.keys[0], KeyHelp { action: String::from("a"), key: Key::Six });
+        assert_eq!(info.gamepad_mapping().unwrap().key(GamepadButton::Up), Some(Key::Five));
+
+        assert!(database().identify(&[0x00, 0xE0]).is_none());
+        assert!(Database::from_json("[]", r#"{ "00": 0 }"#).is_err());
+    }
+
+    #[test]
+    fn embedded_database() {
+        const IBM_LOGO: [u8; 132] = [
+            0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39,
+            0xD0, 0x1F, 0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F,
+            0x70, 0x08, 0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28,
+            0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00,
+            0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF,
+            0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00,
+            0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39,
+            0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+            0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+            0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+        ];
+
+        let database = Database::embedded();
+        assert!(!database.is_empty());
+
+        let info = database.identify(&IBM_LOGO).unwrap();
+        assert_eq!(info.title, "IBM Logo");
+        assert_eq!(info.platform(), Some(Platform::OriginalChip8));
+        assert_eq!(info.quirks, Platform::OriginalChip8.quirks());
+    }
+
+    #[test]
+    fn load_applies_settings() {
+        let mut program = Program::new();
+        let (load, info) = database().load(&mut program, &ROM).unwrap();
+
+        assert_eq!(load.size, 4);
+        assert!(info.is_some());
+        assert_eq!(program.quirks(), Quirks { jump: false, ..Platform::Superchip.quirks() });
+        assert_eq!(program.config().get_instructions_per_frame(), 30);
+
+        // Nothing is left of these settings once an unknown ROM is loaded.
+        let (_, info) = database().load(&mut program, &[0x00, 0xE0]).unwrap();
+        assert!(info.is_none());
+        assert_eq!(program.platform(), None);
+        assert_eq!(program.quirks(), Quirks::default());
+        assert_eq!(program.config().get_instructions_per_frame(), 10);
+    }
+
+    #[test]
+    fn load_applies_font_and_quirks_without_platform() {
+        let rom = [0x00, 0xE0];
+        let sha1 = checksum::to_hex(&checksum::sha1(&rom));
+        let programs = format!(
+            r#"[{{ "title": "Font", "roms": {{ "{}": {{ "fontStyle": "vip" }} }} }}]"#,
+            sha1
+        );
+        let database = Database::from_json(&programs, &format!(r#"{{ "{}": 0 }}"#, sha1))
+            .unwrap();
+
+        let quirks = Quirks { shift: true, ..Quirks::default() };
+        let mut program = EmulatorConfig::new().quirks(quirks).build().unwrap();
+        let (_, info) = database.load(&mut program, &rom).unwrap();
+        let font = program.config().get_font_address() as usize;
+
+        // The entry's quirks apply even though it doesn't name a platform.
+        assert_eq!(program.platform(), None);
+        assert_eq!(program.quirks(), info.unwrap().quirks);
+        assert_eq!(program.config().get_font(), Font::Vip);
+        assert_eq!(program.memory()[font..font + 5], Font::Vip.glyphs()[0]);
+
+        database.load(&mut program, &ROM).unwrap();
+        assert_eq!(program.config().get_font(), Font::Octo);
+        assert_eq!(program.memory()[font..font + 5], Font::Octo.glyphs()[0]);
+    }
+}
@@ -1,10 +1,35 @@
+use chip8_core::analysis;
+use chip8_core::filter::{ Persistence, PhosphorFilter };
+use chip8_core::gif::GifRecorder;
+use chip8_core::keypad::{ self, GamepadButton, GamepadMapping, Key, KeyboardLayout };
+use chip8_core::megachip::MegaChip;
+use chip8_core::platform::Platform;
 use chip8_core::program::Program as InnerProgram;
+use chip8_core::render::{ Color, Palette, Renderer };
+use chip8_core::scale::Scaler;
+use chip8_core::screenshot;
+use chip8_core::state::ExecutionState;
+use chip8_core::sys::SysPolicy;
+use chip8_core::timing::Timing;
+use chip8_db::{ Database, RomInfo };
 use serde::Serialize;
+use std::convert::TryFrom;
+use std::num::NonZeroUsize;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub struct Program {
-    inner: InnerProgram
+    inner: InnerProgram<'static>,
+    /// Machine running MegaChip ROMs, around a program of its own.
+    megachip: Option<MegaChip>,
+    renderer: Renderer,
+    filter: PhosphorFilter,
+    scaler: Scaler,
+    recorder: Option<GifRecorder>,
+    layout: KeyboardLayout,
+    gamepad: GamepadMapping,
+    info: Option<RomInfo>,
+    platform: Option<Platform>
 }
 
 #[derive(Serialize)]
@@ -16,57 +41,464 @@ impl Program {
         console_error_panic_hook::set_once();
 
         Program {
-            inner: InnerProgram::new()
+            inner: InnerProgram::new(),
+            megachip: None,
+            renderer: Renderer::default(),
+            filter: PhosphorFilter::default(),
+            scaler: Scaler::default(),
+            recorder: None,
+            layout: KeyboardLayout::default(),
+            gamepad: GamepadMapping::default(),
+            info: None,
+            platform: None
         }
     }
 
-    pub fn load(&mut self, rom: &[u8]) {
-        self.inner.load(rom);
+    /// Load `rom`, applying the quirks, speed, palette and gamepad bindings recommended for it
+    /// if it is in the database. Otherwise the default palette and gamepad bindings are used.
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), JsValue> {
+        let database = Database::embedded();
+        let info = database.identify(rom);
+        let analysis = match info {
+            Some(_) => None,
+            None => {
+                self.inner.restore_platform();
+                let start = self.inner.config().get_start_address();
+                Some(analysis::analyze(rom, start)).filter(|analysis| analysis.is_confident())
+            },
+        };
+        let platform = match &info {
+            Some(info) => info.platform(),
+            None => analysis.as_ref().map(|analysis| analysis.platform),
+        };
+
+        // MegaChip ROMs need their own machine, with a larger memory and a colour display.
+        if platform == Some(Platform::MegaChip8) {
+            let config = match &info {
+                Some(info) => info.configure(*self.inner.config()),
+                None => *self.inner.config(),
+            };
+            let mut megachip = MegaChip::with_config(config).map_err(js_error)?;
+            megachip.load(rom).map_err(js_error)?;
+            self.megachip = Some(megachip);
+        } else {
+            self.megachip = None;
+
+            match &analysis {
+                // Setting the platform first loads the ROM at its start address.
+                Some(analysis) => {
+                    self.inner.set_platform(analysis.platform);
+                    self.inner.set_quirks(analysis.quirks);
+                    self.inner.load(rom).map_err(js_error)?;
+                },
+                None => {
+                    database.load(&mut self.inner, rom).map_err(js_error)?;
+                },
+            }
+        }
+
+        // Don't keep the palette and bindings of the previous ROM.
+        let palette = info.as_ref().and_then(|info| info.palette).unwrap_or_default();
+        self.renderer.set_palette(palette);
+        self.gamepad = info.as_ref().and_then(RomInfo::gamepad_mapping).unwrap_or_default();
+
+        self.platform = platform;
+        self.info = info;
+        self.filter.reset();
+        Ok(())
+    }
+
+    /// Name of the platform the loaded ROM was written for, if it is known or could be guessed.
+    pub fn platform(&self) -> Option<String> {
+        self.platform.map(|platform| platform.name().to_string())
+    }
+
+    /// Title of the loaded ROM, if it is in the database.
+    pub fn title(&self) -> Option<String> {
+        self.info.as_ref().map(|info| info.title.clone())
+    }
+
+    /// Keys used by the loaded ROM, as `action: key` lines.
+    pub fn key_help(&self) -> String {
+        self.info.iter()
+            .flat_map(|info| info.keys.iter())
+            .map(|help| format!("{}: {}\n", help.action, help.key))
+            .collect()
     }
 
     pub fn tick(&mut self) {
-        self.inner.run();
+        match self.megachip.as_mut() {
+            Some(megachip) => megachip.run(),
+            None => self.inner.run(),
+        };
+    }
+
+    /// Choose what happens to `0NNN` machine code calls, either `fault` or `ignore`.
+    pub fn set_sys_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        let policy = match policy {
+            "fault" => SysPolicy::Fault,
+            "ignore" => SysPolicy::Ignore,
+            _ => return Err(JsValue::from_str(&format!("unknown policy {}", policy)))
+        };
+
+        self.program_mut().set_sys_policy(policy);
+        Ok(())
+    }
+
+    /// One of `running`, `waiting`, `halted` or `faulted`.
+    pub fn state(&self) -> String {
+        let state = match self.program().state() {
+            ExecutionState::Running => "running",
+            ExecutionState::WaitingForKey { .. } => "waiting",
+            ExecutionState::Halted(_) => "halted",
+            ExecutionState::Faulted(_) => "faulted"
+        };
+
+        state.to_string()
+    }
+
+    /// Whether the program halted or faulted, in which case ticking is useless.
+    pub fn is_stopped(&self) -> bool {
+        self.program().state().is_stopped()
     }
 
+    /// Description of why the program halted or faulted.
+    pub fn stop_reason(&self) -> Option<String> {
+        match self.program().state() {
+            ExecutionState::Halted(reason) => Some(reason.to_string()),
+            ExecutionState::Faulted(fault) => Some(fault.to_string()),
+            _ => None
+        }
+    }
+
+    #[allow(deprecated)]
     pub fn screen(&self) -> JsValue {
-        let screen: Vec<_> = self.inner.screen.iter()
-            .map(|line| line.to_vec())
+        let screen: Vec<_> = self.program().screen.rows()
+            .map(|line| line.collect())
             .collect();
         let screen = Screen(screen);
 
         JsValue::from_serde(&screen).unwrap()
     }
 
+    pub fn width(&self) -> usize {
+        match &self.megachip {
+            Some(megachip) => megachip.dimensions().0,
+            None => self.inner.screen.width(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match &self.megachip {
+            Some(megachip) => megachip.dimensions().1,
+            None => self.inner.screen.height(),
+        }
+    }
+
+    /// Width of the image returned by `render(scale)`.
+    pub fn image_width(&self, scale: usize) -> usize {
+        self.width() * self.scale_factor() * scale
+    }
+
+    /// Height of the image returned by `render(scale)`.
+    pub fn image_height(&self, scale: usize) -> usize {
+        self.height() * self.scale_factor() * scale
+    }
+
+    /// RGBA8 pixels of the screen, ready to be wrapped in an `ImageData` of
+    /// `image_width(scale)` by `image_height(scale)` pixels. The colour display of MegaChip
+    /// isn't filtered nor upscaled.
+    pub fn render(&mut self, scale: usize) -> Result<Vec<u8>, JsValue> {
+        self.renderer.set_scale(checked_scale(scale)?);
+
+        if let Some(megachip) = &self.megachip {
+            let pixels = megachip.to_rgba(*self.renderer.palette());
+            return Ok(self.renderer.render_rgba(megachip.dimensions().0, &pixels));
+        }
+
+        // The filter has nothing to show until it sees a frame of the current size.
+        let filtered = self.filter.persistence() != Persistence::None
+            && self.filter.intensities().dimensions() == (self.width(), self.height());
+
+        let pixels = if !filtered {
+            let frame = self.scaler.scale(&self.inner.screen);
+            self.renderer.render(&frame)
+        } else {
+            let intensities = self.scaler.scale_intensities(self.filter.intensities());
+            self.renderer.render_intensities(&intensities)
+        };
+
+        Ok(pixels)
+    }
+
+    /// RGBA8 pixels of the screen coloured by the CHIP-8X colour board, `width() * scale` by
+    /// `height() * scale` pixels.
+    pub fn render_colors(&self, scale: usize) -> Result<Vec<u8>, JsValue> {
+        let renderer = Renderer::new(checked_scale(scale)?, *self.renderer.palette());
+        let program = self.program();
+        Ok(renderer.render_rgba(self.width(), &program.colors.to_rgba(&program.screen)))
+    }
+
+    /// Select the upscaler applied before rendering, one of `nearest`, `epx`, `scale2x` or
+    /// `scale3x`.
+    pub fn set_scaler(&mut self, name: &str) -> Result<(), JsValue> {
+        self.scaler = match name {
+            "nearest" => Scaler::default(),
+            "epx" => Scaler::Epx,
+            "scale2x" => Scaler::Scale2x,
+            "scale3x" => Scaler::Scale3x,
+            _ => return Err(JsValue::from_str(&format!("unknown scaler {}", name)))
+        };
+
+        Ok(())
+    }
+
+    /// Make turned off pixels fade out, losing `amount` of intensity (out of 255) per frame.
+    pub fn set_decay(&mut self, amount: u8) {
+        // Decay is valid with any amount.
+        let _ = self.filter.set_persistence(Persistence::Decay { amount });
+    }
+
+    /// Keep pixels lit if they were lit on any of the last `frames` frames.
+    pub fn set_blend(&mut self, frames: u8) -> Result<(), JsValue> {
+        self.filter.set_persistence(Persistence::Blend { frames }).map_err(js_error)
+    }
+
+    pub fn disable_filter(&mut self) {
+        let _ = self.filter.set_persistence(Persistence::None);
+    }
+
+    /// Set a monochrome palette from two `0xRRGGBB` colors.
+    pub fn set_palette(&mut self, background: u32, foreground: u32) {
+        let palette = Palette::monochrome(Color::from_hex(background), Color::from_hex(foreground));
+        self.renderer.set_palette(palette);
+    }
+
+    /// Set the four colors used by multi-plane modes, as `0xRRGGBB` values.
+    pub fn set_palette4(&mut self, first: u32, second: u32, third: u32, fourth: u32) {
+        let palette = Palette::new([
+            Color::from_hex(first),
+            Color::from_hex(second),
+            Color::from_hex(third),
+            Color::from_hex(fourth)
+        ]);
+        self.renderer.set_palette(palette);
+    }
+
+    /// PNG screenshot of the screen, using the current palette and upscaler.
+    pub fn screenshot_png(&self, scale: usize) -> Result<Vec<u8>, JsValue> {
+        let scale = checked_scale(scale)?;
+        let renderer = Renderer::new(scale, *self.renderer.palette());
+
+        if let Some(megachip) = &self.megachip {
+            let (width, height) = megachip.dimensions();
+            let pixels = renderer.render_rgba(width, &megachip.to_rgba(*renderer.palette()));
+            let (width, height) = (width * scale.get(), height * scale.get());
+            return Ok(screenshot::encode_png(width, height, &pixels));
+        }
+
+        Ok(screenshot::png(&self.scaler.scale(&self.inner.screen), &renderer))
+    }
+
+    /// PBM screenshot of the screen, using the current upscaler.
+    pub fn screenshot_pbm(&self, scale: usize) -> Result<Vec<u8>, JsValue> {
+        Ok(screenshot::pbm(&self.scaler.scale(&self.program().screen), checked_scale(scale)?))
+    }
+
+    /// Start recording every frame, using the current palette.
+    pub fn start_recording(&mut self, scale: usize) -> Result<(), JsValue> {
+        let renderer = Renderer::new(checked_scale(scale)?, *self.renderer.palette());
+        self.recorder = Some(GifRecorder::new(renderer));
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Stop the recording and return it as an animated GIF, empty if nothing was recorded.
+    pub fn stop_recording(&mut self) -> Vec<u8> {
+        match self.recorder.take() {
+            Some(recorder) if !recorder.is_empty() => recorder.encode(),
+            _ => Vec::new()
+        }
+    }
+
     pub fn pc(&self) -> u16 {
-        self.inner.program_counter
+        self.program().program_counter
     }
 
     pub fn memory(&self) -> Vec<u8> {
-        self.inner.memory.to_vec()
+        match &self.megachip {
+            Some(megachip) => megachip.memory().to_vec(),
+            None => self.inner.memory().to_vec(),
+        }
+    }
+
+    /// Press the keypad key with the given value, from `0x0` to `0xF`.
+    pub fn keydown(&mut self, key: u8) -> Result<(), JsValue> {
+        self.program_mut().keydown(checked_key(key)?);
+        Ok(())
+    }
+
+    pub fn keyup(&mut self, key: u8) -> Result<(), JsValue> {
+        self.program_mut().keyup(checked_key(key)?);
+        Ok(())
+    }
+
+    /// Press a key of the second keypad of the CHIP-8X.
+    pub fn second_keydown(&mut self, key: u8) -> Result<(), JsValue> {
+        self.program_mut().second_keydown(checked_key(key)?);
+        Ok(())
+    }
+
+    pub fn second_keyup(&mut self, key: u8) -> Result<(), JsValue> {
+        self.program_mut().second_keyup(checked_key(key)?);
+        Ok(())
+    }
+
+    /// Whether the program is waiting for a key to be pressed and released.
+    pub fn waiting_for_key(&self) -> bool {
+        self.program().waiting_for_key()
     }
 
-    pub fn keydown(&mut self, key: usize) {
-        self.inner.keydown(key);
+    /// Select the layout used by `keyboard_event`, one of `qwerty`, `azerty`, `qwertz` or
+    /// `dvorak`.
+    pub fn set_keyboard_layout(&mut self, name: &str) -> Result<(), JsValue> {
+        self.layout = match name {
+            "qwerty" => KeyboardLayout::Qwerty,
+            "azerty" => KeyboardLayout::Azerty,
+            "qwertz" => KeyboardLayout::Qwertz,
+            "dvorak" => KeyboardLayout::Dvorak,
+            _ => return Err(JsValue::from_str(&format!("unknown keyboard layout {}", name)))
+        };
+
+        Ok(())
     }
 
-    pub fn keyup(&mut self, key: usize) {
-        self.inner.keyup(key);
+    /// Handle the `key` and `code` of a browser `KeyboardEvent`, the character typed being
+    /// mapped with the current layout and the physical key being used as a fallback. Returns
+    /// whether the event was mapped to the keypad.
+    pub fn keyboard_event(&mut self, key: &str, code: &str, pressed: bool) -> bool {
+        let key = self.layout.key_from_str(key).or_else(|| keypad::key_from_code(code));
+
+        self.set_key(key, pressed)
+    }
+
+    /// Handle a button of a gamepad with the standard mapping, returns whether it is bound to
+    /// the keypad.
+    pub fn gamepad_event(&mut self, button: usize, pressed: bool) -> bool {
+        let key = GamepadButton::from_index(button).and_then(|button| self.gamepad.key(button));
+
+        self.set_key(key, pressed)
+    }
+
+    /// Bind a button of a gamepad with the standard mapping to a keypad key.
+    pub fn bind_gamepad_button(&mut self, button: usize, key: u8) -> Result<(), JsValue> {
+        let button = GamepadButton::from_index(button)
+            .ok_or_else(|| JsValue::from_str(&format!("invalid gamepad button {}", button)))?;
+
+        self.gamepad.bind(button, checked_key(key)?);
+        Ok(())
     }
 
     pub fn delay_timer(&self) -> u8 {
-        self.inner.delay_timer
+        self.program().delay_timer
     }
 
     pub fn sound_timer(&self) -> u8 {
-        self.inner.sound_timer
+        self.program().sound_timer
     }
 
     pub fn decrement_timers(&mut self) {
-        if self.inner.delay_timer > 0 {
-            self.inner.delay_timer -= 1;
+        self.program_mut().decrement_timers();
+        self.end_frame();
+    }
+
+    /// Run one 60 Hz frame, as configured by `set_timing`, then decrement the timers.
+    pub fn run_frame(&mut self) {
+        match self.megachip.as_mut() {
+            Some(megachip) => megachip.run_frame(),
+            None => self.inner.run_frame(),
+        };
+        self.end_frame();
+    }
+
+    /// Choose how many instructions `run_frame` executes: `instructions` for the configured
+    /// count, or `vip` for as many as the COSMAC VIP would.
+    pub fn set_timing(&mut self, timing: &str) -> Result<(), JsValue> {
+        let timing = match timing {
+            "instructions" => Timing::Instructions,
+            "vip" => Timing::Vip,
+            _ => return Err(JsValue::from_str(&format!("unknown timing {}", timing)))
+        };
+
+        self.program_mut().set_timing(timing);
+        Ok(())
+    }
+}
+
+impl Program {
+    /// The program holding the registers, timers, keypad and monochrome screen.
+    fn program(&self) -> &InnerProgram<'static> {
+        match &self.megachip {
+            Some(megachip) => megachip.program(),
+            None => &self.inner,
         }
-        if self.inner.sound_timer > 0 {
-            self.inner.sound_timer -= 1;
+    }
+
+    fn program_mut(&mut self) -> &mut InnerProgram<'static> {
+        match self.megachip.as_mut() {
+            Some(megachip) => megachip.program_mut(),
+            None => &mut self.inner,
+        }
+    }
+
+    /// Output pixels per screen pixel of the upscaler, which MegaChip doesn't use.
+    fn scale_factor(&self) -> usize {
+        match self.megachip {
+            Some(_) => 1,
+            None => self.scaler.factor(),
         }
     }
+
+    fn end_frame(&mut self) {
+        let screen = match &self.megachip {
+            Some(megachip) => &megachip.program().screen,
+            None => &self.inner.screen,
+        };
+        self.filter.update(screen);
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.capture(screen);
+        }
+    }
+
+    fn set_key(&mut self, key: Option<Key>, pressed: bool) -> bool {
+        match key {
+            Some(key) if pressed => self.program_mut().keydown(key),
+            Some(key) => self.program_mut().keyup(key),
+            None => return false
+        }
+
+        true
+    }
+}
+
+fn js_error<E: ToString>(error: E) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
+fn checked_key(key: u8) -> Result<Key, JsValue> {
+    Key::try_from(key).map_err(|error| JsValue::from_str(&error.to_string()))
+}
+
+fn checked_scale(scale: usize) -> Result<NonZeroUsize, JsValue> {
+    NonZeroUsize::new(scale).ok_or_else(|| JsValue::from_str("scale factor must be at least 1"))
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::new()
+    }
 }